@@ -1,23 +1,43 @@
 from pyperscan._pyperscan import (
     BlockDatabase,
     BlockScanner,
+    BlockScannerPool,
+    CpuFeature,
+    ExpressionInfo,
     Flag,
+    Match,
+    MatchIterator,
     Pattern,
+    Platform,
     Scan,
     StreamDatabase,
     StreamScanner,
+    StreamTable,
+    TuneFamily,
     VectoredDatabase,
     VectoredScanner,
+    serialized_database_info,
+    serialized_database_size,
 )
 
 __all__ = [
     "BlockDatabase",
     "BlockScanner",
+    "BlockScannerPool",
+    "CpuFeature",
+    "ExpressionInfo",
     "Flag",
+    "Match",
+    "MatchIterator",
     "Pattern",
+    "Platform",
     "Scan",
     "StreamDatabase",
     "StreamScanner",
+    "StreamTable",
+    "TuneFamily",
     "VectoredDatabase",
     "VectoredScanner",
+    "serialized_database_info",
+    "serialized_database_size",
 ]
@@ -1,24 +1,128 @@
+import os
 from array import array
 from collections.abc import Collection
 from mmap import mmap
-from typing import Any, Generic, Protocol, TypeAlias, TypeVar
+from typing import (
+    Any,
+    Generic,
+    Literal,
+    Protocol,
+    Self,
+    TypeAlias,
+    TypeVar,
+    overload,
+)
 
 BufferType: TypeAlias = array[int] | bytes | bytearray | memoryview | mmap
+"""Objects exporting C-contiguous memory through the buffer protocol.
+
+The buffer is held while it is scanned, so e.g. a `bytearray` cannot be resized from
+a callback. Non-contiguous buffers raise `BufferError`, scan a copy of them instead.
+"""
+TaggedMatch: TypeAlias = tuple[Any, int, int]
 _TContext_contra = TypeVar("_TContext_contra", contravariant=True)
 _TScanner = TypeVar("_TScanner", BlockScanner, VectoredScanner, StreamScanner)
 
+class SupportsReadinto(Protocol):
+    """Binary file-like object to read from."""
+
+    def readinto(self, buffer: memoryview, /) -> int: ...
+
 class Pattern:
     """Pattern to search matches for."""
 
-    def __new__(cls, expression: bytes, *flags: Flag, tag: Any = None):
+    def __new__(
+        cls,
+        expression: bytes,
+        *flags: Flag,
+        tag: Any = None,
+        id: int | None = None,  # noqa: A002
+        literal: bool = False,
+        min_offset: int | None = None,
+        max_offset: int | None = None,
+        min_length: int | None = None,
+        edit_distance: int | None = None,
+        hamming_distance: int | None = None,
+    ):
         """Construct a new search pattern.
 
         Args:
             expression: Regular expression.
             flags: modify expression matching behavior.
             tag: Python object to pass to callback when match succeeds.
-                If unset, the pattern index is used.
+                If unset, the pattern id is used.
+            id: Hyperscan ID of the pattern, which logical
+                [COMBINATION][pyperscan._pyperscan.Flag.COMBINATION] expressions
+                refer to. Must be unique within a database. Defaults to the index of
+                the pattern in the database, or the next id not used by another
+                pattern.
+            literal: treat `expression` as a literal byte string instead of a regular
+                expression. Literals may contain any byte, including `\\x00`.
+            min_offset: the minimum end offset in the data stream at which this
+                expression should match successfully.
+            max_offset: the maximum end offset in the data stream at which this
+                expression should match successfully.
+            min_length: the minimum match length (from start to end) required to
+                successfully match this expression.
+            edit_distance: match this expression within a given Levenshtein distance.
+            hamming_distance: match this expression within a given Hamming distance.
+
+        Note:
+            Keyword arguments other than `tag` are passed to Hyperscan as
+            [extended parameters](https://intel.github.io/hyperscan/dev-reference/compilation.html#extparam).
+            Approximate matching (`edit_distance` or `hamming_distance`) cannot be
+            combined with [SOM_LEFTMOST][pyperscan._pyperscan.Flag.SOM_LEFTMOST], and
+            only one of them can be set for a pattern. These are reported as
+            [HyperscanCompileError][pyperscan._pyperscan.HyperscanCompileError] when
+            the database is compiled.
+
+            Literal patterns are compiled with
+            [hs_compile_lit_multi](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_compile_lit_multi).
+            They only support the `CASELESS`, `SINGLEMATCH` and `SOM_LEFTMOST` flags, do
+            not accept extended parameters and cannot be mixed with regular expressions
+            in the same database.
         """
+    def info(self) -> ExpressionInfo:
+        """Analyze the pattern without compiling it into a database.
+
+        Useful to validate patterns individually, e.g. in pre-commit checks.
+
+        Raises:
+            HyperscanCompileError: the pattern cannot be compiled.
+
+        Note:
+            Calls [hs_expression_ext_info](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_expression_ext_info)
+            internally.
+        """
+    @property
+    def expression(self) -> bytes:
+        """The expression to match."""
+    @property
+    def flags(self) -> list[Flag]:
+        """Flags the pattern was created with."""
+    @property
+    def tag(self) -> Any:
+        """Tag of the pattern, if set."""
+    @property
+    def id(self) -> int | None:  # noqa: A003
+        """Explicit Hyperscan ID of the pattern, if set."""
+
+class ExpressionInfo:
+    """Properties of a pattern returned by [Pattern.info][pyperscan._pyperscan.Pattern.info]."""
+
+    min_width: int
+    """The minimum length in bytes of a match for the pattern."""
+    max_width: int | None
+    """The maximum length in bytes of a match for the pattern.
+
+    `None` if the pattern can match arbitrarily long data.
+    """
+    unordered_matches: bool
+    """Whether the pattern can produce matches that are not returned in order."""
+    matches_at_eod: bool
+    """Whether the pattern can produce matches at end of data."""
+    matches_only_at_eod: bool
+    """Whether the pattern can *only* produce matches at end of data."""
 
 class Flag:
     """Pattern compile flags."""
@@ -156,8 +260,8 @@ class Flag:
 
     This flag instructs Hyperscan to parse this expression as logical combination
     syntax.  Logical constraints consist of operands, operators and parentheses.  The
-    operands are expression indices, and operators can be `!` (NOT), `&` (AND) or `|`
-    (OR).  For example: `(101&102&103)|(104&!105)` `((301|302)&303)&(304|305)`
+    operands are expression ids (see [Pattern][pyperscan._pyperscan.Pattern]), and
+    operators can be `!` (NOT), `&` (AND) or `|` (OR).  For example: `(101&102&103)|(104&!105)` `((301|302)&303)&(304|305)`
 
     See
     [HS_FLAGS_COMBINATION](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.HS_FLAG_COMBINATION)
@@ -172,6 +276,77 @@ class Flag:
     [HS_FLAGS_QUIET](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.HS_FLAG_QUIET)
     """
 
+class TuneFamily:
+    """CPU microarchitecture families to tune databases for.
+
+    See [HS_TUNE_FAMILY_*](https://intel.github.io/hyperscan/dev-reference/api_constants.html#cpu-tuning-flags)
+    """
+
+    GENERIC: TuneFamily = ...
+    """Generic, not tuned for any particular microarchitecture."""
+    SNB: TuneFamily = ...
+    """Intel Sandy Bridge."""
+    IVB: TuneFamily = ...
+    """Intel Ivy Bridge."""
+    HSW: TuneFamily = ...
+    """Intel Haswell."""
+    SLM: TuneFamily = ...
+    """Intel Silvermont."""
+    BDW: TuneFamily = ...
+    """Intel Broadwell."""
+    SKL: TuneFamily = ...
+    """Intel Skylake."""
+    SKX: TuneFamily = ...
+    """Intel Skylake Server."""
+    GLM: TuneFamily = ...
+    """Intel Goldmont."""
+    ICL: TuneFamily = ...
+    """Intel Icelake."""
+    ICX: TuneFamily = ...
+    """Intel Icelake Server."""
+
+class CpuFeature:
+    """CPU features databases can make use of.
+
+    See [HS_CPU_FEATURES_*](https://intel.github.io/hyperscan/dev-reference/api_constants.html#cpu-feature-support-flags)
+    """
+
+    AVX2: CpuFeature = ...
+    """Intel(R) Advanced Vector Extensions 2 (Intel(R) AVX2)."""
+    AVX512: CpuFeature = ...
+    """Intel(R) Advanced Vector Extensions 512 (Intel(R) AVX512)."""
+    AVX512VBMI: CpuFeature = ...
+    """Intel(R) Advanced Vector Extensions 512 Vector Byte Manipulation Instructions."""
+
+class Platform:
+    """Target platform to compile databases for.
+
+    Databases compiled for another platform can be serialized with
+    [Database.to_bytes][pyperscan._pyperscan.Database.to_bytes] and deployed there.
+    """
+
+    def __new__(cls, *features: CpuFeature, tune: TuneFamily = TuneFamily.GENERIC):
+        """Describe a platform explicitly.
+
+        Args:
+            features: CPU features the target platform supports.
+            tune: microarchitecture to tune the database for.
+        """
+    @staticmethod
+    def host() -> Platform:
+        """Describe the platform this process is running on.
+
+        Note:
+            Calls [hs_populate_platform](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_populate_platform)
+            internally.
+        """
+    @property
+    def tune(self) -> TuneFamily:
+        """Microarchitecture the database is tuned for."""
+    @property
+    def features(self) -> list[CpuFeature]:
+        """CPU features the target platform supports."""
+
 class OnMatch(Protocol, Generic[_TContext_contra]):
     """Callback called on match."""
 
@@ -185,7 +360,8 @@ class OnMatch(Protocol, Generic[_TContext_contra]):
 
         Args:
             context: Object passed to [Database.build][pyperscan._pyperscan.Database.build]
-            tag: [Pattern.tag][pyperscan._pyperscan.Pattern] of the pattern matched.
+            tag: [Pattern.tag][pyperscan._pyperscan.Pattern] of the pattern matched,
+                or its id if it has no tag.
             start: start index of the matched pattern.
             end: end index of the matched pattern.
 
@@ -193,38 +369,369 @@ class OnMatch(Protocol, Generic[_TContext_contra]):
             Instructs Hyperscan wether to continue or stop searching for subsequent matches.
         """
 
+class OnMatchObject(Protocol, Generic[_TContext_contra]):
+    """Callback called on match with a [Match][pyperscan._pyperscan.Match] object."""
+
+    def __call__(self, context: _TContext_contra, match: Match, /) -> Scan:
+        """Called when a match happens.
+
+        Args:
+            context: Object passed to [Database.build][pyperscan._pyperscan.Database.build]
+            match: details of the match.
+
+        Returns:
+            Instructs Hyperscan wether to continue or stop searching for subsequent matches.
+        """
+
+class Match:
+    """A match passed to `OnMatchObject` callbacks."""
+
+    tag: Any
+    """[Pattern.tag][pyperscan._pyperscan.Pattern] of the pattern matched, or its id
+    if it has no tag."""
+    pattern: Pattern | None
+    """The pattern matched, `None` if the database is restored from
+    `hs_serialize_database` output without its patterns."""
+    start: int | None
+    """Start index of the match, `None` if the pattern is not compiled with
+    [SOM_LEFTMOST][pyperscan._pyperscan.Flag.SOM_LEFTMOST], is unknown or starts
+    before the `som_horizon` of the stream."""
+    end: int
+    """End index of the match."""
+    start_element: tuple[int, int] | None
+    """`(index, offset)` of the start of the match within the elements passed to
+    [VectoredScanner.scan][pyperscan._pyperscan.VectoredScanner.scan], `None` if
+    `start` is `None` or the scan is not vectored."""
+    end_element: tuple[int, int] | None
+    """`(index, offset)` of the end of the match within the elements passed to
+    [VectoredScanner.scan][pyperscan._pyperscan.VectoredScanner.scan], `None` if
+    the scan is not vectored.
+
+    A match spanning element boundaries has a different index in `start_element`
+    than in `end_element`. The end offset is exclusive, so a match ending at the
+    boundary of two elements is reported at the end of the first one."""
+
+    def span(self) -> tuple[int | None, int]:
+        """The `(start, end)` tuple of the match."""
+    def group(self) -> bytes | None:
+        """The matched bytes.
+
+        Only available when `start` is known and the matched bytes are contained in
+        the data passed to the `scan` call reporting the match. Stream matches
+        spanning multiple `scan` calls return `None`.
+        """
+
 class Database(Generic[_TScanner]):
     """A Hyperscan pattern database."""
 
-    def __new__(cls, *patterns: Pattern):
+    def __new__(
+        cls,
+        *patterns: Pattern,
+        platform: Platform | None = None,
+        collect_errors: bool = False,
+    ):
         """Compiles a Hyperscan pattern database.
 
         Args:
             patterns: Expressions to compile into the database to later match against.
+            platform: target platform to compile the database for. Defaults to the
+                host the database is compiled on.
+            collect_errors: when compilation fails, compile each pattern individually
+                to report every invalid pattern in a
+                [HyperscanCompileErrors][pyperscan._pyperscan.HyperscanCompileErrors]
+                exception, instead of just the first one.
 
         Note:
             Calls [hs_compile_ext_multi](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_compile_ext_multi)
             internally.
         """
+    @overload
+    def build(
+        self,
+        context: _TContext_contra,
+        on_match: OnMatch[_TContext_contra],
+        match_objects: Literal[False] = False,
+    ) -> _TScanner: ...
+    @overload
     def build(
-        self, context: _TContext_contra, on_match: OnMatch[_TContext_contra]
+        self,
+        context: _TContext_contra,
+        on_match: OnMatchObject[_TContext_contra],
+        match_objects: Literal[True],
+    ) -> _TScanner: ...
+    def build(
+        self,
+        context: _TContext_contra,
+        on_match: OnMatch[_TContext_contra] | OnMatchObject[_TContext_contra],
+        match_objects: bool = False,
     ) -> _TScanner:
         """Build a scanner object that is usable to search for pattern procurances.
 
         Args:
             context: arbitrary object which is passed as a first parameter to `on_match`.
             on_match: callable to call when a match happens upon `scan` call.
+            match_objects: call `on_match` with a [Match][pyperscan._pyperscan.Match]
+                object instead of the tag and offsets of the match.
+        """
+    @property
+    def info(self) -> str:
+        """Human readable description of the database.
+
+        Contains the Hyperscan version, the CPU features and the scanning mode it was
+        compiled for.
+
+        Note:
+            Calls [hs_database_info](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_database_info)
+            internally.
+        """
+    @property
+    def size(self) -> int:
+        """Size of the compiled database in bytes.
+
+        Note:
+            Calls [hs_database_size](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_database_size)
+            internally.
+        """
+    def to_bytes(self) -> bytes:
+        """Serialize the compiled database, including its patterns.
+
+        The result can be restored with `from_bytes` to skip recompiling the patterns.
+        Databases can also be pickled, which also supports tags of any type.
+
+        Raises:
+            TypeError: a pattern has a tag other than `str`, `int` or `bytes`.
+
+        Note:
+            Calls [hs_serialize_database](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_serialize_database)
+            internally.
+        """
+    @classmethod
+    def from_bytes(cls, data: BufferType, tags: dict[int, Any] | None = None) -> Self:
+        """Restore a database serialized by `to_bytes`.
+
+        The output of `hs_serialize_database` is accepted as well, the patterns of such
+        databases are unknown.
+
+        Args:
+            data: output of [to_bytes][pyperscan._pyperscan.Database.to_bytes].
+            tags: tags of the patterns by pattern id, replacing the serialized ones.
+
+        Raises:
+            ValueError: `data` is truncated or malformed, or `tags` has ids without a
+                pattern in it, which is the case for all ids of `hs_serialize_database`
+                output.
+            HyperscanError: `DbModeError` if `data` was serialized from a database
+                of another mode, or any other error reported by Hyperscan.
+
+        Note:
+            Calls [hs_deserialize_database](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_deserialize_database)
+            internally.
         """
 
 class BlockDatabase(Database[BlockScanner]):
     """A database for block (non-streaming) scanning."""
 
+    def scan_many(
+        self, buffers: Collection[BufferType], threads: int | None = None
+    ) -> list[tuple[int, Any, int, int]]:
+        """Scan many independent buffers (blocks) in parallel.
+
+        The GIL is released while the buffers are split between native threads, each
+        scanning with its own scratch space.
+
+        Args:
+            buffers: buffers to search matches in. Can be any object implementing the
+                buffer protocol.
+            threads: number of threads to scan with. Defaults to the available
+                parallelism of the host.
+
+        Returns:
+            `(index, tag, start, end)` tuples, where `index` is the position of the
+                buffer the match is found in. Matches are ordered by `index`, then in
+                the order they were reported.
+        """
+    def build_pool(
+        self,
+        context: _TContext_contra,
+        on_match: OnMatch[_TContext_contra] | OnMatchObject[_TContext_contra],
+        match_objects: bool = False,
+    ) -> BlockScannerPool:
+        """Build a scanner that can be shared between threads.
+
+        Arguments are the same as for
+        [Database.build][pyperscan._pyperscan.Database.build].
+        """
+
+    def finditer(self, data: BufferType) -> MatchIterator:
+        """Iterate over the matches in a single buffer (block).
+
+        Scanning happens on a background thread without holding the GIL, matches are
+        handed over in batches as the iteration goes on. Each call starts a new thread.
+        Abandoning the iterator, e.g. by breaking out of a `for` loop, or closing it
+        terminates the scan and releases `data`. As Hyperscan can only be interrupted
+        from a match, this waits for the scan to reach the next match or the end of
+        `data`, which takes as long as scanning the rest of `data` if there are no
+        more matches.
+
+        Args:
+            data: buffer to search matches in. Can be any object implementing the buffer protocol.
+
+        Returns:
+            Iterator of `(tag, start, end)` tuples, see
+                [BlockScanner.scan_collect][pyperscan._pyperscan.BlockScanner.scan_collect].
+        """
+
 class VectoredDatabase(Database[VectoredScanner]):
     """A databes for vectored scanning."""
 
 class StreamDatabase(Database[StreamScanner]):
     """A database for stream scanning."""
 
+    def __new__(
+        cls,
+        *patterns: Pattern,
+        som_horizon: Literal["small", "medium", "large"] | None = None,
+        platform: Platform | None = None,
+        collect_errors: bool = False,
+    ):
+        """Compiles a Hyperscan pattern database for stream scanning.
+
+        Args:
+            patterns: Expressions to compile into the database to later match against.
+            som_horizon: how far back in the stream start of match offsets are tracked
+                for patterns with
+                [SOM_LEFTMOST][pyperscan._pyperscan.Flag.SOM_LEFTMOST]: `"small"`
+                (2^16 bytes), `"medium"` (2^32 bytes) or `"large"` (2^64 bytes).
+                Larger horizons need more stream state. When unset, `"large"` is used
+                if any of the patterns has `SOM_LEFTMOST` set.
+            platform: target platform to compile the database for. Defaults to the
+                host the database is compiled on.
+            collect_errors: when compilation fails, compile each pattern individually
+                to report every invalid pattern in a
+                [HyperscanCompileErrors][pyperscan._pyperscan.HyperscanCompileErrors]
+                exception, instead of just the first one.
+
+        Note:
+            Calls [hs_compile_ext_multi](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_compile_ext_multi)
+            internally, see
+            [HS_MODE_SOM_HORIZON_*](https://intel.github.io/hyperscan/dev-reference/api_constants.html#c.HS_MODE_SOM_HORIZON_LARGE).
+        """
+
+    @property
+    def stream_size(self) -> int:
+        """Size of the state of each stream opened from this database in bytes.
+
+        Note:
+            Calls [hs_stream_size](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_stream_size)
+            internally.
+        """
+    def build_table(
+        self,
+        context: _TContext_contra,
+        on_match: OnFlowMatch[_TContext_contra] | OnFlowMatchObject[_TContext_contra],
+        match_objects: bool = False,
+        max_streams: int | None = None,
+    ) -> StreamTable:
+        """Build a table of independent streams sharing a single scratch space.
+
+        Args:
+            context: arbitrary object which is passed as a first parameter to `on_match`.
+            on_match: callable to call when a match happens, with the key of the
+                stream the match happens in.
+            match_objects: call `on_match` with a [Match][pyperscan._pyperscan.Match]
+                object instead of the tag and offsets of the match.
+            max_streams: when set, opening a stream in a full table closes the least
+                recently used stream first.
+        """
+
+class OnFlowMatch(Protocol, Generic[_TContext_contra]):
+    """Callback called on match in a `StreamTable` stream."""
+
+    def __call__(
+        self, context: _TContext_contra, key: Any, tag: Any, start: int, end: int, /
+    ) -> Scan:
+        """Same as [OnMatch][pyperscan._pyperscan.OnMatch], with the key of the stream."""
+
+class OnFlowMatchObject(Protocol, Generic[_TContext_contra]):
+    """Callback called on match in a `StreamTable` stream with a `Match` object."""
+
+    def __call__(self, context: _TContext_contra, key: Any, match: Match, /) -> Scan:
+        """Same as [OnMatchObject][pyperscan._pyperscan.OnMatchObject], with the key of
+        the stream."""
+
+class StreamTable:
+    """Independent streams keyed by an arbitrary hashable flow key.
+
+    Created from `StreamDatabase.build_table`. Streams are scanned serially with a
+    single scratch space, instead of one per `StreamScanner`. Closing a stream,
+    explicitly or by eviction, reports matches that can only happen at the end of
+    data.
+    """
+
+    def open(self, key: Any) -> None:
+        """Open a new stream for `key`.
+
+        Raises:
+            ValueError: a stream is already open for `key`.
+        """
+    def scan(self, key: Any, data: BufferType) -> Scan:
+        """Scan for matches in the stream of `key`.
+
+        See [StreamScanner.scan][pyperscan._pyperscan.StreamScanner.scan].
+
+        Raises:
+            KeyError: no stream is open for `key`.
+        """
+    def close(self, key: Any) -> Scan:
+        """Close the stream of `key`.
+
+        See [StreamScanner.close][pyperscan._pyperscan.StreamScanner.close].
+
+        Raises:
+            KeyError: no stream is open for `key`.
+        """
+    def evict_idle(self, seconds: float) -> int:
+        """Close streams not scanned for at least `seconds`.
+
+        All idle streams are closed even if closing some of them raises, the first
+        exception is raised afterwards.  A stream whose key raises from `__hash__`
+        stays open.
+
+        Returns:
+            The number of streams closed.
+        """
+    def __len__(self) -> int: ...
+    def __contains__(self, key: object) -> bool: ...
+
+def serialized_database_info(data: BufferType) -> str:
+    """Describe a serialized database without deserializing it.
+
+    Args:
+        data: output of [Database.to_bytes][pyperscan._pyperscan.Database.to_bytes]
+            or `hs_serialize_database`. Only the Hyperscan database is read, not
+            the patterns.
+
+    Returns:
+        The same string as [Database.info][pyperscan._pyperscan.Database.info].
+
+    Note:
+        Calls [hs_serialized_database_info](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_serialized_database_info)
+        internally.
+    """
+
+def serialized_database_size(data: BufferType) -> int:
+    """Memory needed to deserialize a serialized database, in bytes.
+
+    Args:
+        data: output of [Database.to_bytes][pyperscan._pyperscan.Database.to_bytes]
+            or `hs_serialize_database`. Only the Hyperscan database is read, not
+            the patterns.
+
+    Note:
+        Calls [hs_serialized_database_size](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_serialized_database_size)
+        internally.
+    """
+
 class Scan:
     """Match callback return value to instruct Hyperscan wether to contine or terminate scanning."""
 
@@ -233,9 +740,30 @@ class Scan:
     Terminate: Scan = ...
     """Terminate scanning."""
 
+class MatchIterator:
+    """Created from [BlockDatabase.finditer][pyperscan._pyperscan.BlockDatabase.finditer]."""
+
+    def __iter__(self) -> Self: ...
+    def __next__(self) -> TaggedMatch: ...
+    def close(self) -> None:
+        """Terminate the scan and release the scanned buffer.
+
+        Waits for the background thread to stop at the next match or the end of the
+        data, the iterator is exhausted afterwards.
+        """
+
 class BlockScanner:
     """Created from `BlockDatabase` for block scanning."""
 
+    @property
+    def scratch_size(self) -> int:
+        """Size of the scratch space allocated for this scanner in bytes.
+
+        Note:
+            Calls [hs_scratch_size](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_scratch_size)
+            internally.
+        """
+
     def scan(self, data: BufferType) -> Scan:
         """Scan for matches in a single buffer (block).
 
@@ -244,11 +772,74 @@ class BlockScanner:
 
         Returns:
             Indicates if scanning is terminated from `OnMatch` callback.
+
+        Raises:
+            ValueError: `data` is `4 GiB` or larger, which Hyperscan cannot scan as
+                a single block.
         """
+    def scan_file(self, path: str | os.PathLike[str]) -> Scan:
+        """Scan the contents of a file as a single block.
+
+        Regular files are mapped into memory with `mmap` instead of being read. Files
+        reporting no size, like FIFOs or the ones in `/proc`, are read whole.
+
+        Args:
+            path: path of the file to scan.
+
+        Returns:
+            Indicates if scanning is terminated from `OnMatch` callback.
+        """
+    def scan_collect(self, data: BufferType) -> list[TaggedMatch]:
+        """Scan a single buffer (block) and return every match.
+
+        Matches are collected without calling the `OnMatch` callback, so the GIL is
+        released for the whole scan.
+
+        Args:
+            data: buffer to search matches in. Can be any object implementing the buffer protocol.
+
+        Returns:
+            `(tag, start, end)` tuples in the order they were reported, where `tag` is
+                the same as the one passed to `OnMatch`.
+        """
+
+class BlockScannerPool:
+    """Created from `BlockDatabase.build_pool` for block scanning from multiple threads.
+
+    Each thread scanning concurrently gets its own scratch space, cloned on demand and
+    reused by later scans.
+
+    Note:
+        Scratch spaces are cloned with
+        [hs_clone_scratch](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_clone_scratch).
+    """
+
+    @property
+    def scratch_size(self) -> int:
+        """Size of a single scratch space of this pool in bytes.
+
+        Note:
+            Calls [hs_scratch_size](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_scratch_size)
+            internally.
+        """
+
+    def scan(self, data: BufferType) -> Scan:
+        """Same as [BlockScanner.scan][pyperscan._pyperscan.BlockScanner.scan]."""
+    def scan_collect(self, data: BufferType) -> list[TaggedMatch]:
+        """Same as [BlockScanner.scan_collect][pyperscan._pyperscan.BlockScanner.scan_collect]."""
 
 class VectoredScanner:
     """Created from `VectoredDatabase` for scanning."""
 
+    @property
+    def scratch_size(self) -> int:
+        """Size of the scratch space allocated for this scanner in bytes.
+
+        Note:
+            Calls [hs_scratch_size](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_scratch_size)
+            internally.
+        """
+
     def scan(self, data: Collection[BufferType]) -> Scan:
         """Scan for matches in a multiple buffers (vector).
 
@@ -257,11 +848,41 @@ class VectoredScanner:
 
         Returns:
             Indicates if scanning is terminated from `OnMatch` callback.
+
+        Raises:
+            ValueError: an element of `data` is `4 GiB` or larger, or `data` has more
+                than `2**32 - 1` elements.
+        """
+    def scan_collect(self, data: Collection[BufferType]) -> list[TaggedMatch]:
+        """Scan multiple buffers (vector) and return every match.
+
+        See [BlockScanner.scan_collect][pyperscan._pyperscan.BlockScanner.scan_collect].
+
+        Args:
+            data: buffer to search matches in. Can be any object implementing the buffer protocol.
+
+        Returns:
+            `(tag, start, end)` tuples in the order they were reported.
         """
 
 class StreamScanner:
     """Created from `StreamDatabase` for stream scanning."""
 
+    @property
+    def scratch_size(self) -> int:
+        """Size of the scratch space allocated for this scanner in bytes.
+
+        Note:
+            Calls [hs_scratch_size](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_scratch_size)
+            internally.
+        """
+    @property
+    def stream_size(self) -> int:
+        """Size of the stream state of this scanner in bytes.
+
+        See [StreamDatabase.stream_size][pyperscan._pyperscan.StreamDatabase.stream_size].
+        """
+
     def scan(self, data: BufferType, chunk_size: int | None = None) -> Scan:
         """Scan for matches in a stream.
 
@@ -274,13 +895,42 @@ class StreamScanner:
             chunk_size: when provided, `data` is scanned in `chunk_size` bits.
 
         Tip:
-            Hyperscan can match only up-to the first `4 GiB` of buffers. Use an
-            arbitrary big buffer with a `chunk_size` less than `4 GiB` to overcome this
-            limitation.
+            Hyperscan takes buffers of less than `4 GiB` per call, bigger buffers are
+            split into chunks below this limit even without `chunk_size`.
 
         Returns:
             Indicates if scanning is terminated from `OnMatch` callback.
         """
+    def scan_collect(
+        self, data: BufferType, chunk_size: int | None = None
+    ) -> list[TaggedMatch]:
+        """Scan the next part of a stream and return every match found in it.
+
+        See [BlockScanner.scan_collect][pyperscan._pyperscan.BlockScanner.scan_collect]
+        and [StreamScanner.scan][pyperscan._pyperscan.StreamScanner.scan].
+
+        Args:
+            data: buffer to search matches in. Can be any object implementing the buffer protocol.
+            chunk_size: when provided, `data` is scanned in `chunk_size` bits.
+
+        Returns:
+            `(tag, start, end)` tuples in the order they were reported.
+        """
+    def scan_reader(self, reader: SupportsReadinto, chunk_size: int = 65536) -> Scan:
+        """Scan everything read from `reader` as the next part of the stream.
+
+        Chunks are read with `reader.readinto` into a single `bytearray` reused between
+        reads, and scanned without holding the GIL.
+
+        Args:
+            reader: a blocking binary file-like object, e.g. a file opened in `rb`
+                mode or a socket file.
+            chunk_size: maximum number of bytes to read at once.
+
+        Returns:
+            Indicates if scanning is terminated from `OnMatch` callback. Reading stops
+                at the end of `reader` or when scanning is terminated.
+        """
     def reset(self) -> Scan:
         """Reset stream scanning to its initial state.
 
@@ -288,6 +938,83 @@ class StreamScanner:
         subsequent `scan` operation will act as the first call, counting match index
         from zero.
         """
+    def copy(self) -> StreamScanner:
+        """Branch the stream into an independent scanner.
+
+        The copy continues from the current state of the stream with the same context
+        and `OnMatch` callback, so a common prefix can be scanned once and followed by
+        alternative continuations.
+
+        Note:
+            Calls [hs_copy_stream](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_copy_stream)
+            internally.
+        """
+    def reset_from(self, other: StreamScanner) -> Scan:
+        """Replace the state of the stream with a copy of the state of `other`.
+
+        Reuses the stream of this scanner instead of allocating a new one. Matches
+        that can only happen at the end of data are reported for the replaced state,
+        as in [close][pyperscan._pyperscan.StreamScanner.close].
+
+        Args:
+            other: scanner built from the same database.
+
+        Raises:
+            ValueError: `other` is built from a different database, or is this scanner.
+
+        Note:
+            Calls [hs_reset_and_copy_stream](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_reset_and_copy_stream)
+            internally.
+        """
+    def save_state(self) -> bytes:
+        """Compress the state of the stream, e.g. to park an idle stream in storage.
+
+        The state can be restored with `restore_state` into a scanner built from the
+        same database, possibly in another process.
+
+        Note:
+            Calls [hs_compress_stream](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_compress_stream)
+            internally. The state includes a fingerprint of the database, computed by
+            serializing it when a state is first saved or restored by the scanner.
+        """
+    def restore_state(self, state: bytes) -> Scan:
+        """Replace the state of the stream with one saved by `save_state`.
+
+        Matches that can only happen at the end of data are reported for the replaced
+        state, as in [close][pyperscan._pyperscan.StreamScanner.close]. A closed
+        stream is reopened.
+
+        Args:
+            state: output of [save_state][pyperscan._pyperscan.StreamScanner.save_state].
+
+        Raises:
+            ValueError: `state` is truncated, or saved from a scanner of a different
+                database.
+
+        Note:
+            Calls [hs_reset_and_expand_stream](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_reset_and_expand_stream)
+            or [hs_expand_stream](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_expand_stream)
+            internally.
+        """
+    def close(self) -> Scan:
+        """Close the stream, reporting matches that can only happen at the end of data.
+
+        Patterns anchored to the end of data, like `foo$`, are reported through the
+        `OnMatch` callback here. Subsequent `scan` and `reset` calls raise
+        `ValueError`, closing an already closed stream does nothing.
+
+        Scanners are context managers closing the stream on exit. Streams that are not
+        closed explicitly are discarded without reporting end of data matches.
+
+        Note:
+            Calls [hs_close_stream](https://intel.github.io/hyperscan/dev-reference/api_files.html#c.hs_close_stream)
+            internally.
+        """
+    @property
+    def closed(self) -> bool:
+        """Whether the stream is closed."""
+    def __enter__(self) -> Self: ...
+    def __exit__(self, *args: object) -> Literal[False]: ...
 
 class HyperscanErrorCode:
     """List of errors can be returned by the low level Hyperscan operations.
@@ -400,3 +1127,18 @@ class HyperscanCompileError:
 
     args: tuple[str, int]
     """Contains a human readable message and the index of the offending pattern."""
+
+class HyperscanCompileErrors(HyperscanCompileError):
+    """One or more patterns to be compiled are invalid.
+
+    Raised instead of [HyperscanCompileError][pyperscan._pyperscan.HyperscanCompileError]
+    whenever compiling a database constructed with `collect_errors=True` fails.
+    """
+
+    args: tuple[str, int]
+    """Contains a human readable summary and the index of the first offending
+    pattern."""
+    errors: list[tuple[int, Any, bytes, str]]
+    """The index, tag, expression and error message of each offending pattern.
+
+    Errors not specific to a pattern have an index of `-1`."""
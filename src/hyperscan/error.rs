@@ -14,6 +14,27 @@ pub(crate) enum Error {
     #[error("Pattern compilation failed, {0} at {1}")]
     HyperscanCompile(String, i32),
 
+    #[error("Pattern compilation failed for {} patterns", .0.len())]
+    HyperscanCompileMany(Vec<(String, i32, Vec<u8>)>),
+
+    #[error("Stream is closed")]
+    StreamClosed,
+
+    #[error("Stream belongs to a different database")]
+    DatabaseMismatch,
+
+    #[error("Stream state is truncated")]
+    TruncatedState,
+
+    #[error("Buffer of {0} bytes exceeds the 4 GiB scan limit")]
+    BufferTooLarge(usize),
+
+    #[error("{0} buffers exceed the vectored scan limit")]
+    TooManyBuffers(usize),
+
+    #[error("Match event handler panicked: {0}")]
+    Panic(String),
+
     #[error("Exception raised from Python callback")]
     Python(#[from] PyErr),
 }
@@ -4,4 +4,6 @@ mod wrapper;
 
 pub(crate) use error::{AsResult, Error, HyperscanErrorCode};
 pub(crate) use native::*;
-pub(crate) use wrapper::{Flag, Pattern, ScanMode};
+pub(crate) use wrapper::{
+    CpuFeatures, ExprExt, ExpressionInfo, Flag, Pattern, Platform, ScanMode, SomHorizon, TuneFamily,
+};
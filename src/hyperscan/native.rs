@@ -1,8 +1,23 @@
 use foreign_types::ForeignType;
 use hyperscan_sys as hs;
-use std::{ffi::c_void, sync::Arc};
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    ffi::c_void,
+    mem,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use super::{wrapper, AsResult, Error, HyperscanErrorCode, Pattern, ScanMode};
+use super::{
+    wrapper, AsResult, Error, Flag, HyperscanErrorCode, Pattern, Platform, ScanMode, SomHorizon,
+};
 
 #[derive(Default, Eq, PartialEq)]
 pub(crate) enum Scan {
@@ -18,6 +33,20 @@ pub(crate) trait MatchEventHandler<T>:
 
 impl<T, F: Fn(&mut T, u32, u64, u64) -> Result<Scan, Error>> MatchEventHandler<T> for F {}
 
+/// Start offset reported for matches starting before the SOM horizon of a stream,
+/// `HS_OFFSET_PAST_HORIZON` in Hyperscan.
+pub(crate) const OFFSET_PAST_HORIZON: u64 = u64::MAX;
+
+/// Info string of a serialized database, without deserializing it.
+pub(crate) fn serialized_database_info(bytes: &[u8]) -> Result<String, Error> {
+    wrapper::Database::serialized_info(bytes)
+}
+
+/// Memory needed to deserialize a serialized database.
+pub(crate) fn serialized_database_size(bytes: &[u8]) -> Result<usize, Error> {
+    wrapper::Database::serialized_size(bytes)
+}
+
 pub(crate) struct BlockDatabase {
     db: Arc<wrapper::Database>,
 }
@@ -29,17 +58,272 @@ pub(crate) struct BlockScanner<U> {
 }
 
 impl BlockDatabase {
-    pub(crate) fn new(patterns: Vec<Pattern>) -> Result<Self, Error> {
-        let db = Arc::new(wrapper::Database::new(patterns, ScanMode::BLOCK)?);
+    pub(crate) fn new(
+        patterns: Vec<Pattern>,
+        platform: Option<&Platform>,
+        collect_errors: bool,
+    ) -> Result<Self, Error> {
+        let db = Arc::new(wrapper::Database::new(
+            &patterns,
+            ScanMode::BLOCK,
+            platform,
+            collect_errors,
+        )?);
         Ok(Self { db })
     }
 
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let db = Arc::new(wrapper::Database::deserialize(bytes, ScanMode::BLOCK)?);
+        Ok(Self { db })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.db.serialize()
+    }
+
+    pub(crate) fn info(&self) -> Result<String, Error> {
+        self.db.info()
+    }
+
+    pub(crate) fn size(&self) -> Result<usize, Error> {
+        self.db.size()
+    }
+
     pub(crate) fn create_scanner<U: 'static>(
         &self,
         context: Context<U>,
     ) -> Result<BlockScanner<U>, Error> {
         BlockScanner::new(self, context)
     }
+
+    /// Scans every buffer on `threads` threads, each with its own clone of the scratch.
+    ///
+    /// Matches are returned with the index of the buffer they are found in, following
+    /// the order of `buffers`.
+    pub(crate) fn scan_many(
+        &self,
+        buffers: &[&[u8]],
+        threads: usize,
+    ) -> Result<Vec<(usize, Match)>, Error> {
+        let chunk_size = ((buffers.len() + threads - 1) / threads).max(1);
+        let prototype = wrapper::Scratch::new(&self.db)?;
+        let scanners = buffers
+            .chunks(chunk_size)
+            .map(|_| {
+                let context =
+                    Context::new((), |_: &mut (), _: u32, _: u64, _: u64| Ok(Scan::Continue));
+                Ok(BlockScanner {
+                    scratch: prototype.try_clone()?,
+                    database: self.db.clone(),
+                    context,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        thread::scope(|scope| {
+            let handles = buffers
+                .chunks(chunk_size)
+                .zip(scanners)
+                .enumerate()
+                .map(|(n, (chunk, mut scanner))| {
+                    scope.spawn(move || {
+                        let mut matches = Vec::new();
+                        for (i, data) in chunk.iter().enumerate() {
+                            let index = n * chunk_size + i;
+                            let found = scanner.scan_collect(data)?;
+                            matches.extend(found.into_iter().map(|m| (index, m)));
+                        }
+                        Ok::<_, Error>(matches)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let mut matches = Vec::new();
+            for handle in handles {
+                matches.extend(handle.join().expect("Scanning thread panicked")?);
+            }
+            Ok(matches)
+        })
+    }
+
+    pub(crate) fn create_scanner_pool<U>(&self) -> Result<BlockScannerPool<U>, Error> {
+        let idle = IdleScanners {
+            prototype: wrapper::Scratch::new(&self.db)?,
+            scanners: Vec::new(),
+        };
+        Ok(BlockScannerPool {
+            database: self.db.clone(),
+            idle: Mutex::new(idle),
+        })
+    }
+
+    /// Scans `data` on a background thread, handing matches over in batches.
+    ///
+    /// The scan is terminated once the returned `MatchIter` is dropped.
+    pub(crate) fn find_iter<D>(&self, data: D) -> Result<MatchIter, Error>
+    where
+        D: Deref<Target = [u8]> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let handler = |batch: &mut MatchBatch, id, from, to| -> Result<Scan, Error> {
+            if batch.cancelled.load(Ordering::Relaxed) {
+                return Ok(Scan::Terminate);
+            }
+            batch.matches.push((id, from, to));
+            if batch.matches.len() < MATCH_BATCH_SIZE {
+                return Ok(Scan::Continue);
+            }
+            let matches = mem::replace(&mut batch.matches, Vec::with_capacity(MATCH_BATCH_SIZE));
+            Ok(batch
+                .sender
+                .send(matches)
+                .map_or(Scan::Terminate, |_| Scan::Continue))
+        };
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let batch = MatchBatch {
+            matches: Vec::with_capacity(MATCH_BATCH_SIZE),
+            sender,
+            cancelled: cancelled.clone(),
+        };
+        let mut scanner = self.create_scanner(Context::new(batch, handler))?;
+        let handle = thread::spawn(move || {
+            let rv = scanner.scan(&data)?;
+            let batch = &mut scanner.context.user_data;
+            if !batch.matches.is_empty() {
+                // The receiving end may be gone already, nothing to do then.
+                let _ = batch.sender.send(mem::take(&mut batch.matches));
+            }
+            Ok(rv)
+        });
+
+        Ok(MatchIter {
+            receiver: Some(receiver),
+            handle: Some(handle),
+            cancelled,
+        })
+    }
+}
+
+/// Block scanners shared between threads, each of them scanning with its own scratch.
+pub(crate) struct BlockScannerPool<U> {
+    database: Arc<wrapper::Database>,
+    idle: Mutex<IdleScanners<U>>,
+}
+
+struct IdleScanners<U> {
+    /// Scratch cloned for new scanners.
+    prototype: wrapper::Scratch,
+    scanners: Vec<BlockScanner<U>>,
+}
+
+impl<U> BlockScannerPool<U> {
+    /// Takes an idle scanner, or creates a new one if all of them are in use.
+    pub(crate) fn acquire(
+        &self,
+        context: impl FnOnce() -> Result<Context<U>, Error>,
+    ) -> Result<BlockScanner<U>, Error> {
+        let mut idle = self.idle.lock().expect("Scanner pool lock poisoned");
+        if let Some(scanner) = idle.scanners.pop() {
+            return Ok(scanner);
+        }
+        Ok(BlockScanner {
+            scratch: idle.prototype.try_clone()?,
+            database: self.database.clone(),
+            context: context()?,
+        })
+    }
+
+    /// Gives back a scanner taken by `acquire`.
+    pub(crate) fn release(&self, scanner: BlockScanner<U>) {
+        self.idle
+            .lock()
+            .expect("Scanner pool lock poisoned")
+            .scanners
+            .push(scanner);
+    }
+
+    pub(crate) fn scratch_size(&self) -> Result<usize, Error> {
+        self.idle
+            .lock()
+            .expect("Scanner pool lock poisoned")
+            .prototype
+            .size()
+    }
+
+    /// Calls `f` with the user data of each idle scanner, or not at all when the
+    /// pool is locked by another thread.
+    pub(crate) fn try_for_each_idle<E>(
+        &self,
+        mut f: impl FnMut(&U) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self.idle.try_lock() {
+            Ok(idle) => idle
+                .scanners
+                .iter()
+                .try_for_each(|scanner| f(&scanner.context.user_data)),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Drops the idle scanners.
+    pub(crate) fn clear_idle(&self) {
+        self.idle
+            .lock()
+            .expect("Scanner pool lock poisoned")
+            .scanners
+            .clear();
+    }
+}
+
+const MATCH_BATCH_SIZE: usize = 256;
+
+struct MatchBatch {
+    matches: Vec<Match>,
+    sender: mpsc::SyncSender<Vec<Match>>,
+    /// Set when the `MatchIter` is cancelled or dropped.
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Receiving end of `BlockDatabase::find_iter`.
+pub(crate) struct MatchIter {
+    receiver: Option<mpsc::Receiver<Vec<Match>>>,
+    handle: Option<thread::JoinHandle<Result<Scan, Error>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl MatchIter {
+    /// Blocks until the next batch of matches is available.
+    ///
+    /// Returns `None` once the scan is finished.
+    pub(crate) fn next_batch(&mut self) -> Result<Option<Vec<Match>>, Error> {
+        if let Some(Ok(matches)) = self.receiver.as_ref().map(|r| r.recv()) {
+            return Ok(Some(matches));
+        }
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .expect("Scanning thread panicked")
+                .map(|_| None),
+            None => Ok(None),
+        }
+    }
+
+    /// Terminates the scan at the next match, and waits for the scanning thread to
+    /// finish. Without further matches, this waits until the whole buffer is scanned.
+    pub(crate) fn cancel(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        // Unblocks the scanning thread waiting for a batch to be received.
+        self.receiver = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for MatchIter {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
 }
 
 pub(crate) struct VectoredDatabase {
@@ -53,11 +337,37 @@ pub(crate) struct VectoredScanner<U> {
 }
 
 impl VectoredDatabase {
-    pub(crate) fn new(patterns: Vec<Pattern>) -> Result<Self, Error> {
-        let db = Arc::new(wrapper::Database::new(patterns, ScanMode::VECTORED)?);
+    pub(crate) fn new(
+        patterns: Vec<Pattern>,
+        platform: Option<&Platform>,
+        collect_errors: bool,
+    ) -> Result<Self, Error> {
+        let db = Arc::new(wrapper::Database::new(
+            &patterns,
+            ScanMode::VECTORED,
+            platform,
+            collect_errors,
+        )?);
+        Ok(Self { db })
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let db = Arc::new(wrapper::Database::deserialize(bytes, ScanMode::VECTORED)?);
         Ok(Self { db })
     }
 
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.db.serialize()
+    }
+
+    pub(crate) fn info(&self) -> Result<String, Error> {
+        self.db.info()
+    }
+
+    pub(crate) fn size(&self) -> Result<usize, Error> {
+        self.db.size()
+    }
+
     pub(crate) fn create_scanner<U: 'static>(
         &self,
         context: Context<U>,
@@ -68,21 +378,89 @@ impl VectoredDatabase {
 
 pub(crate) struct StreamDatabase {
     db: Arc<wrapper::Database>,
+    fingerprint: Fingerprint,
 }
 
 pub(crate) struct StreamScanner<U> {
     scratch: wrapper::Scratch,
-    stream: wrapper::Stream,
+    /// Unset once the stream is closed.
+    stream: Option<wrapper::Stream>,
+    database: Arc<wrapper::Database>,
     context: Context<U>,
+    fingerprint: Fingerprint,
+}
+
+/// Database fingerprint embedded in saved states, computed on first use and shared by
+/// the scanners of the database.
+#[derive(Clone, Default)]
+struct Fingerprint(Arc<Mutex<Option<u64>>>);
+
+impl Fingerprint {
+    fn get(&self, database: &wrapper::Database) -> Result<u64, Error> {
+        let mut fingerprint = self.0.lock().expect("Fingerprint lock poisoned");
+        match *fingerprint {
+            Some(fingerprint) => Ok(fingerprint),
+            None => {
+                let value = database.fingerprint()?;
+                *fingerprint = Some(value);
+                Ok(value)
+            }
+        }
+    }
 }
 
 impl StreamDatabase {
-    pub(crate) fn new(patterns: Vec<Pattern>) -> Result<Self, Error> {
+    /// Compiles a stream database.
+    ///
+    /// Without an explicit `som_horizon`, a large horizon is used only if a pattern
+    /// requests leftmost start of match reporting, as it increases stream state size.
+    pub(crate) fn new(
+        patterns: Vec<Pattern>,
+        som_horizon: Option<SomHorizon>,
+        platform: Option<&Platform>,
+        collect_errors: bool,
+    ) -> Result<Self, Error> {
+        let som_horizon = som_horizon.or_else(|| {
+            patterns
+                .iter()
+                .any(|p| p.flags().contains(Flag::SOM_LEFTMOST))
+                .then_some(SomHorizon::Large)
+        });
+        let mode = som_horizon.map_or(ScanMode::STREAM, |h| ScanMode::STREAM | h.into());
         let db = Arc::new(wrapper::Database::new(
-            patterns,
-            ScanMode::STREAM | ScanMode::SOM_LARGE,
+            &patterns,
+            mode,
+            platform,
+            collect_errors,
         )?);
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            fingerprint: Fingerprint::default(),
+        })
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let db = Arc::new(wrapper::Database::deserialize(bytes, ScanMode::STREAM)?);
+        Ok(Self {
+            db,
+            fingerprint: Fingerprint::default(),
+        })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.db.serialize()
+    }
+
+    pub(crate) fn info(&self) -> Result<String, Error> {
+        self.db.info()
+    }
+
+    pub(crate) fn size(&self) -> Result<usize, Error> {
+        self.db.size()
+    }
+
+    pub(crate) fn stream_size(&self) -> Result<usize, Error> {
+        self.db.stream_size()
     }
 
     pub(crate) fn create_scanner<U: 'static>(
@@ -91,12 +469,147 @@ impl StreamDatabase {
     ) -> Result<StreamScanner<U>, Error> {
         StreamScanner::new(self, context)
     }
+
+    pub(crate) fn create_table<U>(&self, context: Context<U>) -> Result<StreamTable<U>, Error> {
+        Ok(StreamTable {
+            scratch: wrapper::Scratch::new(&self.db)?,
+            database: self.db.clone(),
+            context,
+            streams: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+        })
+    }
+}
+
+/// Independent streams identified by an id, scanned serially with a shared scratch.
+pub(crate) struct StreamTable<U> {
+    scratch: wrapper::Scratch,
+    database: Arc<wrapper::Database>,
+    context: Context<U>,
+    streams: HashMap<u64, TableStream>,
+    /// Stream ids by the tick of their last use, least recently used first.
+    lru: BTreeMap<u64, u64>,
+    tick: u64,
+}
+
+struct TableStream {
+    stream: wrapper::Stream,
+    tick: u64,
+    last_used: Instant,
+}
+
+impl<U> StreamTable<U> {
+    pub(crate) fn user_data(&self) -> &U {
+        &self.context.user_data
+    }
+
+    pub(crate) fn user_data_mut(&mut self) -> &mut U {
+        &mut self.context.user_data
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub(crate) fn open(&mut self, id: u64) -> Result<(), Error> {
+        let stream = wrapper::Stream::new(&self.database)?;
+        self.tick += 1;
+        self.lru.insert(self.tick, id);
+        let stream = TableStream {
+            stream,
+            tick: self.tick,
+            last_used: Instant::now(),
+        };
+        if let Some(replaced) = self.streams.insert(id, stream) {
+            self.lru.remove(&replaced.tick);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn scan(&mut self, id: u64, data: &[u8]) -> Result<Scan, Error> {
+        let entry = self.streams.get_mut(&id).ok_or(Error::StreamClosed)?;
+        self.lru.remove(&entry.tick);
+        self.tick += 1;
+        self.lru.insert(self.tick, id);
+        entry.tick = self.tick;
+        entry.last_used = Instant::now();
+        scan_stream(&entry.stream, &self.scratch, &mut self.context, data)
+    }
+
+    /// Closes a stream, reporting matches that only fire at the end of data.
+    pub(crate) fn close(&mut self, id: u64) -> Result<Scan, Error> {
+        let entry = self.streams.remove(&id).ok_or(Error::StreamClosed)?;
+        self.lru.remove(&entry.tick);
+        close_stream(entry.stream, &self.scratch, &mut self.context)
+    }
+
+    pub(crate) fn least_recently_used(&self) -> Option<u64> {
+        self.lru.values().next().copied()
+    }
+
+    /// Ids of the streams not used for at least `max_idle`, least recently used first.
+    pub(crate) fn idle(&self, max_idle: Duration) -> Vec<u64> {
+        self.lru
+            .values()
+            .take_while(|id| self.streams[id].last_used.elapsed() >= max_idle)
+            .copied()
+            .collect()
+    }
+}
+
+/// Pattern id, start and end offset of a match.
+pub(crate) type Match = (u32, u64, u64);
+
+/// Element index and element-local offset within the buffers of a vectored scan.
+pub(crate) type ElementOffset = (usize, u64);
+
+/// Maps offsets of a vectored scan, which are relative to the concatenation of
+/// all elements, back to the element they fall into.
+#[derive(Default)]
+pub(crate) struct VectorLayout {
+    /// Offset of each element within the concatenation.
+    starts: Vec<u64>,
+}
+
+impl VectorLayout {
+    pub(crate) fn new(data: &[&[u8]]) -> Self {
+        let starts = data
+            .iter()
+            .scan(0u64, |start, d| {
+                let element = *start;
+                *start += d.len() as u64;
+                Some(element)
+            })
+            .collect();
+        Self { starts }
+    }
+
+    /// Element holding the byte at `from`. A start offset at the very end of
+    /// the data belongs to the last element.
+    pub(crate) fn locate_start(&self, from: u64) -> Option<ElementOffset> {
+        let index = self.starts.partition_point(|&start| start <= from);
+        let index = index.checked_sub(1)?;
+        Some((index, from - self.starts[index]))
+    }
+
+    /// Element holding the byte before `to`, as match end offsets are exclusive.
+    /// An end offset of 0 belongs to the first element.
+    pub(crate) fn locate_end(&self, to: u64) -> Option<ElementOffset> {
+        let index = self.starts.partition_point(|&start| start < to);
+        match index.checked_sub(1) {
+            Some(index) => Some((index, to - self.starts[index])),
+            None => (!self.starts.is_empty()).then_some((0, 0)),
+        }
+    }
 }
 
 pub(crate) struct Context<U> {
     user_data: U,
     match_error: Option<Error>,
     match_event_handler: Box<dyn MatchEventHandler<U> + Send>,
+    /// When set, matches are collected here instead of calling the handler.
+    matches: Option<Vec<Match>>,
 }
 
 impl<U> Context<U> {
@@ -108,8 +621,13 @@ impl<U> Context<U> {
             user_data,
             match_error: None,
             match_event_handler: Box::new(match_event_handler),
+            matches: None,
         }
     }
+
+    pub(crate) fn user_data_mut(&mut self) -> &mut U {
+        &mut self.user_data
+    }
 }
 
 impl<U> BlockScanner<U> {
@@ -143,19 +661,48 @@ impl<U> StreamScanner<U> {
 
         Ok(Self {
             scratch,
-            stream,
+            stream: Some(stream),
+            database: db.db.clone(),
             context,
+            fingerprint: db.fingerprint.clone(),
         })
     }
 }
 
 impl<U> StreamScanner<U> {
+    pub(crate) fn scratch_size(&self) -> Result<usize, Error> {
+        self.scratch.size()
+    }
+
+    pub(crate) fn stream_size(&self) -> Result<usize, Error> {
+        self.database.stream_size()
+    }
+
+    pub(crate) fn user_data(&self) -> &U {
+        &self.context.user_data
+    }
+
+    pub(crate) fn user_data_mut(&mut self) -> &mut U {
+        &mut self.context.user_data
+    }
+
+    /// Scans `data`, collecting matches instead of calling the match event handler.
+    pub(crate) fn scan_collect(&mut self, data: &[u8]) -> Result<Vec<Match>, Error> {
+        self.context.matches = Some(Vec::new());
+        let result = self.scan(data);
+        let matches = self.context.matches.take().unwrap_or_default();
+        result.map(|_| matches)
+    }
+
     pub(crate) fn scan(&mut self, data: &[u8]) -> Result<Scan, Error> {
+        let stream = self.stream.as_ref().ok_or(Error::StreamClosed)?;
+        scan_stream(stream, &self.scratch, &mut self.context, data)
+    }
+
+    pub(crate) fn reset(&mut self) -> Result<Scan, Error> {
         unsafe {
-            hs::hs_scan_stream(
-                self.stream.as_ptr(),
-                data.as_ptr() as *const _,
-                data.len() as u32,
+            hs::hs_reset_stream(
+                self.stream()?.as_ptr(),
                 0,
                 self.scratch.as_ptr(),
                 Some(on_match::<U>),
@@ -166,11 +713,69 @@ impl<U> StreamScanner<U> {
         }
     }
 
-    pub(crate) fn reset(&mut self) -> Result<Scan, Error> {
+    /// Creates an independent scanner continuing from the current state of the stream.
+    pub(crate) fn copy(&self, context: Context<U>) -> Result<Self, Error> {
+        Ok(Self {
+            scratch: self.scratch.try_clone()?,
+            stream: Some(self.stream()?.try_clone()?),
+            database: self.database.clone(),
+            context,
+            fingerprint: self.fingerprint.clone(),
+        })
+    }
+
+    /// Replaces the state of the stream with a copy of the state of `other`.
+    ///
+    /// Matches that only fire at the end of data are reported for the replaced state.
+    pub(crate) fn reset_from<V>(&mut self, other: &StreamScanner<V>) -> Result<Scan, Error> {
+        if !Arc::ptr_eq(&self.database, &other.database) {
+            return Err(Error::DatabaseMismatch);
+        }
         unsafe {
-            hs::hs_reset_stream(
-                self.stream.as_ptr(),
-                0,
+            hs::hs_reset_and_copy_stream(
+                self.stream()?.as_ptr(),
+                other.stream()?.as_ptr(),
+                self.scratch.as_ptr(),
+                Some(on_match::<U>),
+                &mut self.context as *mut _ as *mut c_void,
+            )
+            .ok()
+            .to_scan_result(self.context.match_error.take())
+        }
+    }
+
+    /// Compresses the state of the stream, prefixed with the fingerprint of the database.
+    pub(crate) fn save_state(&self) -> Result<Vec<u8>, Error> {
+        let mut state = self.fingerprint.get(&self.database)?.to_le_bytes().to_vec();
+        state.extend(self.stream()?.compress()?);
+        Ok(state)
+    }
+
+    /// Replaces the state of the stream with one saved by `save_state`, reopening the
+    /// stream if it is closed.
+    ///
+    /// Matches that only fire at the end of data are reported for the replaced state.
+    pub(crate) fn restore_state(&mut self, state: &[u8]) -> Result<Scan, Error> {
+        let fingerprint = self.fingerprint.get(&self.database)?.to_le_bytes();
+        if state.len() < fingerprint.len() {
+            return Err(Error::TruncatedState);
+        }
+        let state = match state.strip_prefix(&fingerprint[..]) {
+            Some(state) => state,
+            None => return Err(Error::DatabaseMismatch),
+        };
+        let stream = match &self.stream {
+            Some(stream) => stream,
+            None => {
+                self.stream = Some(wrapper::Stream::expand(&self.database, state)?);
+                return Ok(Scan::Continue);
+            }
+        };
+        unsafe {
+            hs::hs_reset_and_expand_stream(
+                stream.as_ptr(),
+                state.as_ptr() as *const _,
+                state.len(),
                 self.scratch.as_ptr(),
                 Some(on_match::<U>),
                 &mut self.context as *mut _ as *mut c_void,
@@ -179,15 +784,54 @@ impl<U> StreamScanner<U> {
             .to_scan_result(self.context.match_error.take())
         }
     }
+
+    /// Closes the stream, reporting matches that only fire at the end of data.
+    ///
+    /// Closing an already closed stream does nothing.
+    pub(crate) fn close(&mut self) -> Result<Scan, Error> {
+        match self.stream.take() {
+            Some(stream) => close_stream(stream, &self.scratch, &mut self.context),
+            None => Ok(Scan::Continue),
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    fn stream(&self) -> Result<&wrapper::Stream, Error> {
+        self.stream.as_ref().ok_or(Error::StreamClosed)
+    }
 }
 
 impl<U> BlockScanner<U> {
+    pub(crate) fn scratch_size(&self) -> Result<usize, Error> {
+        self.scratch.size()
+    }
+
+    pub(crate) fn user_data(&self) -> &U {
+        &self.context.user_data
+    }
+
+    pub(crate) fn user_data_mut(&mut self) -> &mut U {
+        &mut self.context.user_data
+    }
+
+    /// Scans `data`, collecting matches instead of calling the match event handler.
+    pub(crate) fn scan_collect(&mut self, data: &[u8]) -> Result<Vec<Match>, Error> {
+        self.context.matches = Some(Vec::new());
+        let result = self.scan(data);
+        let matches = self.context.matches.take().unwrap_or_default();
+        result.map(|_| matches)
+    }
+
     pub(crate) fn scan(&mut self, data: &[u8]) -> Result<Scan, Error> {
+        let len = scan_len(data)?;
         unsafe {
             hs::hs_scan(
                 self.database.as_ptr(),
                 data.as_ptr() as *const _,
-                data.len() as u32,
+                len,
                 0,
                 self.scratch.as_ptr(),
                 Some(on_match::<U>),
@@ -200,15 +844,39 @@ impl<U> BlockScanner<U> {
 }
 
 impl<U> VectoredScanner<U> {
+    pub(crate) fn scratch_size(&self) -> Result<usize, Error> {
+        self.scratch.size()
+    }
+
+    pub(crate) fn user_data(&self) -> &U {
+        &self.context.user_data
+    }
+
+    pub(crate) fn user_data_mut(&mut self) -> &mut U {
+        &mut self.context.user_data
+    }
+
+    /// Scans `data`, collecting matches instead of calling the match event handler.
+    pub(crate) fn scan_collect(&mut self, data: Vec<&[u8]>) -> Result<Vec<Match>, Error> {
+        self.context.matches = Some(Vec::new());
+        let result = self.scan(data);
+        let matches = self.context.matches.take().unwrap_or_default();
+        result.map(|_| matches)
+    }
+
     pub(crate) fn scan(&mut self, data: Vec<&[u8]>) -> Result<Scan, Error> {
-        let (len, data): (Vec<_>, Vec<_>) =
-            data.iter().map(|d| (d.len() as u32, d.as_ptr())).unzip();
+        let count = u32::try_from(data.len()).map_err(|_| Error::TooManyBuffers(data.len()))?;
+        let len = data
+            .iter()
+            .map(|d| scan_len(d))
+            .collect::<Result<Vec<_>, _>>()?;
+        let data: Vec<_> = data.iter().map(|d| d.as_ptr()).collect();
         unsafe {
             hs::hs_scan_vector(
                 self.database.as_ptr(),
                 data.as_ptr() as *const *const _,
                 len.as_ptr(),
-                len.len() as u32,
+                count,
                 0,
                 self.scratch.as_ptr(),
                 Some(on_match::<U>),
@@ -220,6 +888,57 @@ impl<U> VectoredScanner<U> {
     }
 }
 
+/// Length of a buffer passed to Hyperscan, which takes 32-bit lengths.
+fn scan_len(data: &[u8]) -> Result<u32, Error> {
+    u32::try_from(data.len()).map_err(|_| Error::BufferTooLarge(data.len()))
+}
+
+/// Scans `data` in chunks within the 4 GiB length limit of Hyperscan, the stream
+/// state carries matches across chunks.
+fn scan_stream<U>(
+    stream: &wrapper::Stream,
+    scratch: &wrapper::Scratch,
+    context: &mut Context<U>,
+    data: &[u8],
+) -> Result<Scan, Error> {
+    for chunk in data.chunks(u32::MAX as usize) {
+        let rv = unsafe {
+            hs::hs_scan_stream(
+                stream.as_ptr(),
+                chunk.as_ptr() as *const _,
+                chunk.len() as u32,
+                0,
+                scratch.as_ptr(),
+                Some(on_match::<U>),
+                context as *mut _ as *mut c_void,
+            )
+            .ok()
+            .to_scan_result(context.match_error.take())?
+        };
+        if rv == Scan::Terminate {
+            return Ok(rv);
+        }
+    }
+    Ok(Scan::Continue)
+}
+
+fn close_stream<U>(
+    stream: wrapper::Stream,
+    scratch: &wrapper::Scratch,
+    context: &mut Context<U>,
+) -> Result<Scan, Error> {
+    unsafe {
+        hs::hs_close_stream(
+            stream.into_ptr(),
+            scratch.as_ptr(),
+            Some(on_match::<U>),
+            context as *mut _ as *mut c_void,
+        )
+        .ok()
+        .to_scan_result(context.match_error.take())
+    }
+}
+
 trait ScanResult: Sized {
     fn to_scan_result(self, inner_err: Option<Error>) -> Result<Scan, Error>;
 }
@@ -247,10 +966,21 @@ unsafe extern "C" fn on_match<U>(
     _flags: u32,
     ctx: *mut c_void,
 ) -> i32 {
-    let context = (ctx as *mut Context<U>)
-        .as_mut()
-        .expect("Context object unset");
-    (context.match_event_handler)(&mut context.user_data, id, from, to).map_or_else(
+    let context = match (ctx as *mut Context<U>).as_mut() {
+        Some(context) => context,
+        // Nowhere to report the error to, stop scanning at least.
+        None => return 1,
+    };
+    if let Some(matches) = context.matches.as_mut() {
+        matches.push((id, from, to));
+        return 0;
+    }
+    // Unwinding into the Hyperscan C frames is undefined behavior.
+    let rv = panic::catch_unwind(AssertUnwindSafe(|| {
+        (context.match_event_handler)(&mut context.user_data, id, from, to)
+    }))
+    .unwrap_or_else(|payload| Err(Error::Panic(panic_message(payload))));
+    rv.map_or_else(
         |err| {
             context.match_error = Some(err);
             -1
@@ -261,3 +991,38 @@ unsafe extern "C" fn on_match<U>(
         },
     )
 }
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or_else(|| "unknown panic".to_owned(), |&message| message.to_owned()),
+    }
+}
+
+// Errors hold Python exceptions, which need libpython to be linked
+#[cfg(all(test, not(feature = "extension-module")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_match_stores_panics_as_errors() {
+        let mut context = Context::new((), |_: &mut (), _, _, _| -> Result<Scan, Error> {
+            panic!("boom")
+        });
+        let ctx = &mut context as *mut Context<()> as *mut c_void;
+
+        let rv = unsafe { on_match::<()>(0, 0, 3, 0, ctx) };
+
+        assert_eq!(rv, -1);
+        assert!(matches!(context.match_error, Some(Error::Panic(ref msg)) if msg == "boom"));
+    }
+
+    #[test]
+    fn on_match_terminates_without_context() {
+        let rv = unsafe { on_match::<()>(0, 0, 3, 0, std::ptr::null_mut()) };
+
+        assert_eq!(rv, 1);
+    }
+}
@@ -1,8 +1,13 @@
-use crate::hyperscan::{AsResult, Error};
+use crate::hyperscan::{AsResult, Error, HyperscanErrorCode};
 use bitflags::bitflags;
 use foreign_types::{foreign_type, ForeignType};
 use hyperscan_sys as hs;
-use std::{ffi::CString, mem::MaybeUninit, ptr};
+use std::{
+    collections::HashSet,
+    ffi::{c_char, c_void, CStr, CString},
+    mem::MaybeUninit,
+    ptr, slice,
+};
 
 foreign_type! {
     unsafe type CompileError: Send {
@@ -26,6 +31,12 @@ foreign_type! {
     }
 }
 
+extern "C" {
+    // Hyperscan allocates serialized databases and info strings with its
+    // misc allocator, which defaults to `malloc`.
+    fn free(ptr: *mut c_void);
+}
+
 unsafe fn stream_drop(stream: *mut hs::hs_stream_t) {
     let _ = hs::hs_close_stream(stream, ptr::null_mut(), None, ptr::null_mut());
 }
@@ -47,38 +58,359 @@ bitflags! {
     }
 }
 
+/// Extended expression parameters, see `hs_expr_ext_t`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ExprExt {
+    pub(crate) min_offset: Option<u64>,
+    pub(crate) max_offset: Option<u64>,
+    pub(crate) min_length: Option<u64>,
+    pub(crate) edit_distance: Option<u32>,
+    pub(crate) hamming_distance: Option<u32>,
+}
+
+impl ExprExt {
+    fn is_empty(&self) -> bool {
+        self.min_offset.is_none()
+            && self.max_offset.is_none()
+            && self.min_length.is_none()
+            && self.edit_distance.is_none()
+            && self.hamming_distance.is_none()
+    }
+
+    /// Rejects parameter combinations Hyperscan cannot compile.
+    fn validate(&self, flags: Flag) -> Result<(), &'static str> {
+        let approximate = self.edit_distance.is_some() || self.hamming_distance.is_some();
+        if self.edit_distance.is_some() && self.hamming_distance.is_some() {
+            Err("edit_distance and hamming_distance cannot be used together")
+        } else if approximate && flags.contains(Flag::SOM_LEFTMOST) {
+            Err("approximate matching is not supported with SOM_LEFTMOST")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl From<&ExprExt> for hs::hs_expr_ext_t {
+    fn from(ext: &ExprExt) -> Self {
+        let flags = [
+            (ext.min_offset.is_some(), hs::HS_EXT_FLAG_MIN_OFFSET),
+            (ext.max_offset.is_some(), hs::HS_EXT_FLAG_MAX_OFFSET),
+            (ext.min_length.is_some(), hs::HS_EXT_FLAG_MIN_LENGTH),
+            (ext.edit_distance.is_some(), hs::HS_EXT_FLAG_EDIT_DISTANCE),
+            (
+                ext.hamming_distance.is_some(),
+                hs::HS_EXT_FLAG_HAMMING_DISTANCE,
+            ),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | u64::from(flag));
+        Self {
+            flags,
+            min_offset: ext.min_offset.unwrap_or_default(),
+            max_offset: ext.max_offset.unwrap_or_default(),
+            min_length: ext.min_length.unwrap_or_default(),
+            edit_distance: ext.edit_distance.unwrap_or_default(),
+            hamming_distance: ext.hamming_distance.unwrap_or_default(),
+        }
+    }
+}
+
 pub(crate) struct Pattern {
     expression: Vec<u8>,
     flags: Flag,
+    ext: ExprExt,
+    literal: bool,
     id: Option<u32>,
 }
 
 impl Pattern {
-    pub(crate) fn new(expression: Vec<u8>, flags: Flag, id: Option<u32>) -> Self {
+    pub(crate) fn new(
+        expression: Vec<u8>,
+        flags: Flag,
+        ext: ExprExt,
+        literal: bool,
+        id: Option<u32>,
+    ) -> Self {
         Self {
             expression,
             flags,
+            ext,
+            literal,
             id,
         }
     }
+
+    pub(crate) fn flags(&self) -> Flag {
+        self.flags
+    }
+
+    /// Analyzes the pattern without compiling it into a database.
+    pub(crate) fn info(&self) -> Result<ExpressionInfo, Error> {
+        self.validate()
+            .map_err(|msg| Error::HyperscanCompile(msg.into(), 0))?;
+        if self.literal {
+            let width = self.expression.len() as u32;
+            return Ok(ExpressionInfo {
+                min_width: width,
+                max_width: Some(width),
+                unordered_matches: false,
+                matches_at_eod: false,
+                matches_only_at_eod: false,
+            });
+        }
+
+        let c_expr = CString::new(self.expression.clone())?;
+        let c_ext = hs::hs_expr_ext_t::from(&self.ext);
+        let mut info = MaybeUninit::uninit();
+        let mut err = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_expression_ext_info(
+                c_expr.as_ptr(),
+                self.flags.bits(),
+                if self.ext.is_empty() {
+                    ptr::null()
+                } else {
+                    &c_ext
+                },
+                info.as_mut_ptr(),
+                err.as_mut_ptr(),
+            )
+            .ok()
+            .map_err(|_| err.assume_init())?;
+            let info = info.assume_init();
+            let expression_info = ExpressionInfo::from(&*info);
+            free(info as *mut c_void);
+            Ok(expression_info)
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if !self.literal {
+            return self.ext.validate(self.flags);
+        }
+        if !(Flag::CASELESS | Flag::SINGLEMATCH | Flag::SOM_LEFTMOST).contains(self.flags) {
+            Err("literal patterns only support CASELESS, SINGLEMATCH and SOM_LEFTMOST flags")
+        } else if !self.ext.is_empty() {
+            Err("literal patterns do not support extended parameters")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Properties of a single expression, see `hs_expr_info_t`.
+pub(crate) struct ExpressionInfo {
+    pub(crate) min_width: u32,
+    /// `None` when the expression can match arbitrarily long data.
+    pub(crate) max_width: Option<u32>,
+    pub(crate) unordered_matches: bool,
+    pub(crate) matches_at_eod: bool,
+    pub(crate) matches_only_at_eod: bool,
+}
+
+impl From<&hs::hs_expr_info_t> for ExpressionInfo {
+    fn from(info: &hs::hs_expr_info_t) -> Self {
+        Self {
+            min_width: info.min_width,
+            max_width: (info.max_width != u32::MAX).then_some(info.max_width),
+            unordered_matches: info.unordered_matches != 0,
+            matches_at_eod: info.matches_at_eod != 0,
+            matches_only_at_eod: info.matches_only_at_eod != 0,
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Default)]
+    pub(crate) struct CpuFeatures: u64 {
+        const AVX2 = hs::HS_CPU_FEATURES_AVX2 as u64;
+        const AVX512 = hs::HS_CPU_FEATURES_AVX512 as u64;
+        const AVX512VBMI = hs::HS_CPU_FEATURES_AVX512VBMI as u64;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TuneFamily {
+    Generic,
+    Snb,
+    Ivb,
+    Hsw,
+    Slm,
+    Bdw,
+    Skl,
+    Skx,
+    Glm,
+    Icl,
+    Icx,
+}
+
+impl From<TuneFamily> for u32 {
+    fn from(tune: TuneFamily) -> Self {
+        match tune {
+            TuneFamily::Generic => hs::HS_TUNE_FAMILY_GENERIC,
+            TuneFamily::Snb => hs::HS_TUNE_FAMILY_SNB,
+            TuneFamily::Ivb => hs::HS_TUNE_FAMILY_IVB,
+            TuneFamily::Hsw => hs::HS_TUNE_FAMILY_HSW,
+            TuneFamily::Slm => hs::HS_TUNE_FAMILY_SLM,
+            TuneFamily::Bdw => hs::HS_TUNE_FAMILY_BDW,
+            TuneFamily::Skl => hs::HS_TUNE_FAMILY_SKL,
+            TuneFamily::Skx => hs::HS_TUNE_FAMILY_SKX,
+            TuneFamily::Glm => hs::HS_TUNE_FAMILY_GLM,
+            TuneFamily::Icl => hs::HS_TUNE_FAMILY_ICL,
+            TuneFamily::Icx => hs::HS_TUNE_FAMILY_ICX,
+        }
+    }
+}
+
+impl From<u32> for TuneFamily {
+    fn from(tune: u32) -> Self {
+        match tune {
+            hs::HS_TUNE_FAMILY_SNB => TuneFamily::Snb,
+            hs::HS_TUNE_FAMILY_IVB => TuneFamily::Ivb,
+            hs::HS_TUNE_FAMILY_HSW => TuneFamily::Hsw,
+            hs::HS_TUNE_FAMILY_SLM => TuneFamily::Slm,
+            hs::HS_TUNE_FAMILY_BDW => TuneFamily::Bdw,
+            hs::HS_TUNE_FAMILY_SKL => TuneFamily::Skl,
+            hs::HS_TUNE_FAMILY_SKX => TuneFamily::Skx,
+            hs::HS_TUNE_FAMILY_GLM => TuneFamily::Glm,
+            hs::HS_TUNE_FAMILY_ICL => TuneFamily::Icl,
+            hs::HS_TUNE_FAMILY_ICX => TuneFamily::Icx,
+            _ => TuneFamily::Generic,
+        }
+    }
+}
+
+/// Target platform to compile databases for, see `hs_platform_info_t`.
+#[derive(Clone, Copy)]
+pub(crate) struct Platform(hs::hs_platform_info_t);
+
+impl Platform {
+    pub(crate) fn new(tune: TuneFamily, cpu_features: CpuFeatures) -> Self {
+        Self(hs::hs_platform_info_t {
+            tune: tune.into(),
+            cpu_features: cpu_features.bits(),
+            reserved1: 0,
+            reserved2: 0,
+        })
+    }
+
+    /// Describes the platform the library is running on.
+    pub(crate) fn host() -> Result<Self, Error> {
+        let mut platform = MaybeUninit::zeroed();
+        unsafe {
+            hs::hs_populate_platform(platform.as_mut_ptr())
+                .ok()
+                .map(|_| Self(platform.assume_init()))
+        }
+    }
+
+    pub(crate) fn tune(&self) -> TuneFamily {
+        self.0.tune.into()
+    }
+
+    pub(crate) fn cpu_features(&self) -> CpuFeatures {
+        CpuFeatures::from_bits_truncate(self.0.cpu_features)
+    }
 }
 
 impl Database {
-    pub(crate) fn new(patterns: Vec<Pattern>, mode: ScanMode) -> Result<Self, Error> {
+    /// Compiles `patterns` into a database.
+    ///
+    /// When `collect_errors` is set and compilation fails, each pattern is compiled
+    /// individually to report every invalid one instead of just the first.
+    pub(crate) fn new(
+        patterns: &[Pattern],
+        mode: ScanMode,
+        platform: Option<&Platform>,
+        collect_errors: bool,
+    ) -> Result<Self, Error> {
+        match Self::compile(patterns, mode, platform) {
+            Err(Error::HyperscanCompile(msg, index)) if collect_errors => {
+                let mut errors: Vec<_> = patterns
+                    .iter()
+                    .enumerate()
+                    // combinations refer to other patterns, they cannot be compiled alone
+                    .filter(|(_, pattern)| !pattern.flags.contains(Flag::COMBINATION))
+                    .filter_map(|(index, pattern)| {
+                        match Self::compile(slice::from_ref(pattern), mode, platform) {
+                            Err(Error::HyperscanCompile(msg, _)) => {
+                                Some((msg, index as i32, pattern.expression.clone()))
+                            }
+                            _ => None,
+                        }
+                    })
+                    .collect();
+                // e.g. errors of combinations, or ones not specific to a pattern
+                if !errors.iter().any(|(_, i, _)| *i == index) {
+                    let expression = usize::try_from(index)
+                        .ok()
+                        .and_then(|i| patterns.get(i))
+                        .map_or_else(Vec::new, |p| p.expression.clone());
+                    errors.push((msg, index, expression));
+                    errors.sort_by_key(|(_, i, _)| *i);
+                }
+                Err(Error::HyperscanCompileMany(errors))
+            }
+            result => result,
+        }
+    }
+
+    fn compile(
+        patterns: &[Pattern],
+        mode: ScanMode,
+        platform: Option<&Platform>,
+    ) -> Result<Self, Error> {
+        let literal = patterns.first().map_or(false, |p| p.literal);
+        let mut ids = HashSet::with_capacity(patterns.len());
+        for (index, pattern) in patterns.iter().enumerate() {
+            if pattern.literal != literal {
+                return Err(Error::HyperscanCompile(
+                    "literal and regular expression patterns cannot be mixed".into(),
+                    index as i32,
+                ));
+            }
+            if let Some(id) = pattern.id.filter(|id| !ids.insert(*id)) {
+                return Err(Error::HyperscanCompile(
+                    format!("duplicate pattern id {id}"),
+                    index as i32,
+                ));
+            }
+            pattern
+                .validate()
+                .map_err(|msg| Error::HyperscanCompile(msg.into(), index as i32))?;
+        }
+
+        if literal {
+            Self::compile_literals(patterns, mode, platform)
+        } else {
+            Self::compile_expressions(patterns, mode, platform)
+        }
+    }
+
+    fn compile_expressions(
+        patterns: &[Pattern],
+        mode: ScanMode,
+        platform: Option<&Platform>,
+    ) -> Result<Self, Error> {
         let mut c_exprs = Vec::with_capacity(patterns.len());
         let mut c_flags = Vec::with_capacity(patterns.len());
         let mut c_ids = Vec::with_capacity(patterns.len());
+        let mut c_exts = Vec::with_capacity(patterns.len());
         for Pattern {
             expression,
             flags,
+            ext,
             id,
+            ..
         } in patterns
         {
             // have to keep the original strings until the db is created
-            let c_expr = CString::new(expression)?;
+            let c_expr = CString::new(expression.as_slice())?;
             c_exprs.push(c_expr);
             c_flags.push(flags.bits());
             c_ids.push(id.unwrap_or(0));
+            c_exts.push((!ext.is_empty()).then(|| hs::hs_expr_ext_t::from(ext)));
         }
 
         let mut db = MaybeUninit::uninit();
@@ -92,10 +424,14 @@ impl Database {
                     .as_ptr(),
                 c_flags.as_ptr(),
                 c_ids.as_ptr(),
-                ptr::null(),
+                c_exts
+                    .iter()
+                    .map(|ext| ext.as_ref().map_or(ptr::null(), |ext| ext as *const _))
+                    .collect::<Vec<_>>()
+                    .as_ptr(),
                 c_exprs.len() as u32,
                 mode.bits(),
-                ptr::null(),
+                platform.map_or(ptr::null(), |platform| &platform.0),
                 db.as_mut_ptr(),
                 err.as_mut_ptr(),
             )
@@ -104,6 +440,165 @@ impl Database {
             Ok(Database::from_ptr(db.assume_init()))
         }
     }
+
+    fn compile_literals(
+        patterns: &[Pattern],
+        mode: ScanMode,
+        platform: Option<&Platform>,
+    ) -> Result<Self, Error> {
+        let mut exprs = Vec::with_capacity(patterns.len());
+        let mut lens = Vec::with_capacity(patterns.len());
+        let mut c_flags = Vec::with_capacity(patterns.len());
+        let mut c_ids = Vec::with_capacity(patterns.len());
+        for Pattern {
+            expression,
+            flags,
+            id,
+            ..
+        } in patterns
+        {
+            // literals are passed with explicit lengths, so they may contain NUL bytes
+            lens.push(expression.len());
+            exprs.push(expression);
+            c_flags.push(flags.bits());
+            c_ids.push(id.unwrap_or(0));
+        }
+
+        let mut db = MaybeUninit::uninit();
+        let mut err = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_compile_lit_multi(
+                exprs
+                    .iter()
+                    .map(|expr| expr.as_ptr() as *const c_char)
+                    .collect::<Vec<_>>()
+                    .as_ptr(),
+                c_flags.as_ptr(),
+                c_ids.as_ptr(),
+                lens.as_ptr(),
+                exprs.len() as u32,
+                mode.bits(),
+                platform.map_or(ptr::null(), |platform| &platform.0),
+                db.as_mut_ptr(),
+                err.as_mut_ptr(),
+            )
+            .ok()
+            .map_err(|_| err.assume_init())?;
+            Ok(Database::from_ptr(db.assume_init()))
+        }
+    }
+
+    pub(crate) fn serialize(&self) -> Result<Vec<u8>, Error> {
+        let mut bytes = MaybeUninit::uninit();
+        let mut length = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_serialize_database(self.as_ptr(), bytes.as_mut_ptr(), length.as_mut_ptr())
+                .ok()?;
+            let bytes = bytes.assume_init();
+            let serialized =
+                slice::from_raw_parts(bytes as *const u8, length.assume_init()).to_vec();
+            free(bytes as *mut c_void);
+            Ok(serialized)
+        }
+    }
+
+    /// Hash of the serialized database, stable across processes.
+    pub(crate) fn fingerprint(&self) -> Result<u64, Error> {
+        // 64-bit FNV-1a
+        Ok(self
+            .serialize()?
+            .iter()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+            }))
+    }
+
+    /// Restores a serialized database, ensuring it was compiled for `mode`.
+    pub(crate) fn deserialize(bytes: &[u8], mode: ScanMode) -> Result<Self, Error> {
+        if Self::serialized_mode(bytes)? != mode {
+            return Err(hs::HS_DB_MODE_ERROR.into());
+        }
+        let mut db = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_deserialize_database(
+                bytes.as_ptr() as *const c_char,
+                bytes.len(),
+                db.as_mut_ptr(),
+            )
+            .ok()
+            .map(|_| Database::from_ptr(db.assume_init()))
+        }
+    }
+
+    fn serialized_mode(bytes: &[u8]) -> Result<ScanMode, Error> {
+        let info = Self::serialized_info(bytes)?;
+        // The info string ends with `Mode: <BLOCK|STREAM|VECTORED>`
+        match info.rsplit("Mode: ").next() {
+            Some(mode) if mode.starts_with("STREAM") => Ok(ScanMode::STREAM),
+            Some(mode) if mode.starts_with("VECTORED") => Ok(ScanMode::VECTORED),
+            Some(mode) if mode.starts_with("BLOCK") => Ok(ScanMode::BLOCK),
+            _ => Err(hs::HS_INVALID.into()),
+        }
+    }
+
+    pub(crate) fn serialized_info(bytes: &[u8]) -> Result<String, Error> {
+        let mut info = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_serialized_database_info(
+                bytes.as_ptr() as *const c_char,
+                bytes.len(),
+                info.as_mut_ptr(),
+            )
+            .ok()?;
+            Ok(take_info(info.assume_init()))
+        }
+    }
+
+    pub(crate) fn serialized_size(bytes: &[u8]) -> Result<usize, Error> {
+        let mut size = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_serialized_database_size(
+                bytes.as_ptr() as *const c_char,
+                bytes.len(),
+                size.as_mut_ptr(),
+            )
+            .ok()
+            .map(|_| size.assume_init())
+        }
+    }
+
+    pub(crate) fn info(&self) -> Result<String, Error> {
+        let mut info = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_database_info(self.as_ptr(), info.as_mut_ptr()).ok()?;
+            Ok(take_info(info.assume_init()))
+        }
+    }
+
+    pub(crate) fn size(&self) -> Result<usize, Error> {
+        let mut size = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_database_size(self.as_ptr(), size.as_mut_ptr())
+                .ok()
+                .map(|_| size.assume_init())
+        }
+    }
+
+    pub(crate) fn stream_size(&self) -> Result<usize, Error> {
+        let mut size = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_stream_size(self.as_ptr(), size.as_mut_ptr())
+                .ok()
+                .map(|_| size.assume_init())
+        }
+    }
+}
+
+/// Copies and releases an info string allocated by Hyperscan.
+unsafe fn take_info(info: *mut c_char) -> String {
+    let owned = CStr::from_ptr(info).to_string_lossy().into_owned();
+    free(info as *mut c_void);
+    owned
 }
 
 impl Scratch {
@@ -115,6 +610,25 @@ impl Scratch {
                 .map(|_| Scratch::from_ptr(scratch.assume_init()))
         }
     }
+
+    /// Allocates a copy of this scratch, to be used by another thread.
+    pub(crate) fn try_clone(&self) -> Result<Self, Error> {
+        let mut scratch = MaybeUninit::zeroed();
+        unsafe {
+            hs::hs_clone_scratch(self.as_ptr(), scratch.as_mut_ptr())
+                .ok()
+                .map(|_| Scratch::from_ptr(scratch.assume_init()))
+        }
+    }
+
+    pub(crate) fn size(&self) -> Result<usize, Error> {
+        let mut size = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_scratch_size(self.as_ptr(), size.as_mut_ptr())
+                .ok()
+                .map(|_| size.assume_init())
+        }
+    }
 }
 impl Stream {
     pub(crate) fn new(database: &Database) -> Result<Self, Error> {
@@ -125,6 +639,51 @@ impl Stream {
                 .map(|_| Stream::from_ptr(stream.assume_init()))
         }
     }
+
+    /// Recreates a stream from a state compressed by `compress`.
+    pub(crate) fn expand(database: &Database, state: &[u8]) -> Result<Self, Error> {
+        let mut stream = ptr::null_mut();
+        unsafe {
+            hs::hs_expand_stream(
+                database.as_ptr(),
+                &mut stream,
+                state.as_ptr() as *const c_char,
+                state.len(),
+            )
+            .ok()
+            .map(|_| Stream::from_ptr(stream))
+        }
+    }
+
+    pub(crate) fn compress(&self) -> Result<Vec<u8>, Error> {
+        let mut size = 0;
+        unsafe {
+            match hs::hs_compress_stream(self.as_ptr(), ptr::null_mut(), 0, &mut size).ok() {
+                Err(Error::Hyperscan(HyperscanErrorCode::InsufficientSpace, _)) => (),
+                rv => rv?,
+            }
+            let mut state = vec![0u8; size];
+            hs::hs_compress_stream(
+                self.as_ptr(),
+                state.as_mut_ptr() as *mut c_char,
+                state.len(),
+                &mut size,
+            )
+            .ok()?;
+            state.truncate(size);
+            Ok(state)
+        }
+    }
+
+    /// Allocates a copy of this stream, including its current state.
+    pub(crate) fn try_clone(&self) -> Result<Self, Error> {
+        let mut stream = MaybeUninit::uninit();
+        unsafe {
+            hs::hs_copy_stream(stream.as_mut_ptr(), self.as_ptr())
+                .ok()
+                .map(|_| Stream::from_ptr(stream.assume_init()))
+        }
+    }
 }
 
 impl CompileError {
@@ -152,6 +711,24 @@ impl From<*mut hs::hs_compile_error> for Error {
     }
 }
 
+/// Precision of start of match offsets tracked in stream mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SomHorizon {
+    Small,
+    Medium,
+    Large,
+}
+
+impl From<SomHorizon> for ScanMode {
+    fn from(horizon: SomHorizon) -> Self {
+        match horizon {
+            SomHorizon::Small => ScanMode::SOM_SMALL,
+            SomHorizon::Medium => ScanMode::SOM_MEDIUM,
+            SomHorizon::Large => ScanMode::SOM_LARGE,
+        }
+    }
+}
+
 bitflags! {
 pub(crate) struct ScanMode: u32 {
     const BLOCK = hs::HS_MODE_BLOCK;
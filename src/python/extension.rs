@@ -1,17 +1,35 @@
-use std::ops::Deref;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    path::PathBuf,
+    slice, thread,
+    time::Duration,
+};
 
 use super::Buffer;
 use crate::hyperscan::{
-    BlockDatabase, BlockScanner, Context, Error, Flag, HyperscanErrorCode, Pattern, Scan,
-    StreamDatabase, StreamScanner, VectoredDatabase, VectoredScanner,
+    self, BlockDatabase, BlockScanner, BlockScannerPool, Context, CpuFeatures, ElementOffset,
+    Error, ExprExt, ExpressionInfo, Flag, HyperscanErrorCode, Match, MatchIter, Pattern, Platform,
+    Scan, SomHorizon, StreamDatabase, StreamScanner, StreamTable, TuneFamily, VectorLayout,
+    VectoredDatabase, VectoredScanner, OFFSET_PAST_HORIZON,
+};
+use pyo3::{
+    create_exception,
+    exceptions::{PyKeyError, PyRuntimeError, PyTypeError, PyValueError},
+    prelude::*,
+    pyclass::{PyTraverseError, PyVisit},
+    types::{PyByteArray, PyBytes, PyDict, PyInt, PyString, PyTuple},
+    IntoPyObjectExt,
 };
-use pyo3::{create_exception, exceptions::PyValueError, prelude::*, types::PyTuple};
 
-#[pyclass(name = "Pattern", module = "pyperscan._pyperscan", unsendable)]
+#[pyclass(frozen, name = "Pattern", module = "pyperscan._pyperscan")]
 struct PyPattern {
     expression: Vec<u8>,
     tag: Option<PyObject>,
+    id: Option<u32>,
     flags: Flag,
+    ext: ExprExt,
+    literal: bool,
 }
 
 #[allow(non_camel_case_types)]
@@ -57,6 +75,22 @@ impl From<PyScan> for Scan {
     }
 }
 
+impl PyFlag {
+    const ALL: [PyFlag; 11] = [
+        PyFlag::CASELESS,
+        PyFlag::DOTALL,
+        PyFlag::MULTILINE,
+        PyFlag::SINGLEMATCH,
+        PyFlag::ALLOWEMPTY,
+        PyFlag::UTF8,
+        PyFlag::UCP,
+        PyFlag::PREFILTER,
+        PyFlag::SOM_LEFTMOST,
+        PyFlag::COMBINATION,
+        PyFlag::QUIET,
+    ];
+}
+
 impl From<&PyFlag> for Flag {
     fn from(flags: &PyFlag) -> Self {
         match flags {
@@ -78,11 +112,30 @@ impl From<&PyFlag> for Flag {
 #[pymethods]
 impl PyPattern {
     #[new]
-    #[pyo3(signature = (expression, *flags, tag = None))]
+    #[pyo3(signature = (
+        expression,
+        *flags,
+        tag = None,
+        id = None,
+        literal = false,
+        min_offset = None,
+        max_offset = None,
+        min_length = None,
+        edit_distance = None,
+        hamming_distance = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn py_new(
         expression: &'_ [u8],
         flags: &Bound<'_, PyTuple>,
         tag: Option<PyObject>,
+        id: Option<u32>,
+        literal: bool,
+        min_offset: Option<u64>,
+        max_offset: Option<u64>,
+        min_length: Option<u64>,
+        edit_distance: Option<u32>,
+        hamming_distance: Option<u32>,
     ) -> PyResult<Self> {
         let flags = flags
             .iter()
@@ -93,46 +146,622 @@ impl PyPattern {
         Ok(PyPattern {
             expression: expression.into(),
             tag,
+            id,
+            flags,
+            ext: ExprExt {
+                min_offset,
+                max_offset,
+                min_length,
+                edit_distance,
+                hamming_distance,
+            },
+            literal,
+        })
+    }
+
+    fn info(&self) -> PyResult<PyExpressionInfo> {
+        let pattern = Pattern::new(
+            self.expression.clone(),
+            self.flags,
+            self.ext,
+            self.literal,
+            self.id,
+        );
+        Ok(pattern.info()?.into())
+    }
+
+    #[getter]
+    fn expression<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.expression)
+    }
+
+    #[getter]
+    fn flags(&self) -> Vec<PyFlag> {
+        PyFlag::ALL
+            .into_iter()
+            .filter(|f| self.flags.contains(f.into()))
+            .collect()
+    }
+
+    #[getter]
+    fn tag(&self, py: Python<'_>) -> Option<PyObject> {
+        self.tag.as_ref().map(|tag| tag.clone_ref(py))
+    }
+
+    #[getter]
+    fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        visit.call(&self.tag)
+    }
+}
+
+impl PyPattern {
+    /// Appends the pattern to a database serialized by `to_bytes`.
+    ///
+    /// Tags are only written when `tag` is set, as they are passed to `from_bytes`
+    /// separately when pickling.
+    fn write_to(&self, py: Python<'_>, out: &mut Vec<u8>, tag: bool) -> PyResult<()> {
+        write_bytes(out, &self.expression);
+        out.extend_from_slice(&self.flags.bits().to_le_bytes());
+        write_option(out, self.id.map(u64::from));
+        out.push(self.literal.into());
+        write_option(out, self.ext.min_offset);
+        write_option(out, self.ext.max_offset);
+        write_option(out, self.ext.min_length);
+        write_option(out, self.ext.edit_distance.map(u64::from));
+        write_option(out, self.ext.hamming_distance.map(u64::from));
+        match self.tag.as_ref().filter(|_| tag) {
+            None => out.push(TAG_NONE),
+            Some(tag) => write_tag(out, tag.bind(py))?,
+        }
+        Ok(())
+    }
+
+    fn read_from(py: Python<'_>, reader: &mut DatabaseReader<'_>) -> PyResult<Self> {
+        let expression = reader.bytes()?.to_vec();
+        let flags = Flag::from_bits(reader.u32()?).ok_or_else(invalid_database)?;
+        let id = reader.option_u32()?;
+        let literal = reader.bool()?;
+        let ext = ExprExt {
+            min_offset: reader.option_u64()?,
+            max_offset: reader.option_u64()?,
+            min_length: reader.option_u64()?,
+            edit_distance: reader.option_u32()?,
+            hamming_distance: reader.option_u32()?,
+        };
+        let tag = reader.tag(py)?;
+        Ok(PyPattern {
+            expression,
+            tag,
+            id,
             flags,
+            ext,
+            literal,
         })
     }
 }
 
-type TagMapping = Vec<Option<PyObject>>;
+#[pyclass(
+    get_all,
+    frozen,
+    name = "ExpressionInfo",
+    module = "pyperscan._pyperscan"
+)]
+struct PyExpressionInfo {
+    min_width: u32,
+    max_width: Option<u32>,
+    unordered_matches: bool,
+    matches_at_eod: bool,
+    matches_only_at_eod: bool,
+}
+
+impl From<ExpressionInfo> for PyExpressionInfo {
+    fn from(info: ExpressionInfo) -> Self {
+        Self {
+            min_width: info.min_width,
+            max_width: info.max_width,
+            unordered_matches: info.unordered_matches,
+            matches_at_eod: info.matches_at_eod,
+            matches_only_at_eod: info.matches_only_at_eod,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[pyclass(eq, name = "TuneFamily", module = "pyperscan._pyperscan")]
+#[derive(Clone, PartialEq)]
+enum PyTuneFamily {
+    GENERIC,
+    SNB,
+    IVB,
+    HSW,
+    SLM,
+    BDW,
+    SKL,
+    SKX,
+    GLM,
+    ICL,
+    ICX,
+}
+
+impl From<PyTuneFamily> for TuneFamily {
+    fn from(tune: PyTuneFamily) -> Self {
+        match tune {
+            PyTuneFamily::GENERIC => TuneFamily::Generic,
+            PyTuneFamily::SNB => TuneFamily::Snb,
+            PyTuneFamily::IVB => TuneFamily::Ivb,
+            PyTuneFamily::HSW => TuneFamily::Hsw,
+            PyTuneFamily::SLM => TuneFamily::Slm,
+            PyTuneFamily::BDW => TuneFamily::Bdw,
+            PyTuneFamily::SKL => TuneFamily::Skl,
+            PyTuneFamily::SKX => TuneFamily::Skx,
+            PyTuneFamily::GLM => TuneFamily::Glm,
+            PyTuneFamily::ICL => TuneFamily::Icl,
+            PyTuneFamily::ICX => TuneFamily::Icx,
+        }
+    }
+}
+
+impl From<TuneFamily> for PyTuneFamily {
+    fn from(tune: TuneFamily) -> Self {
+        match tune {
+            TuneFamily::Generic => PyTuneFamily::GENERIC,
+            TuneFamily::Snb => PyTuneFamily::SNB,
+            TuneFamily::Ivb => PyTuneFamily::IVB,
+            TuneFamily::Hsw => PyTuneFamily::HSW,
+            TuneFamily::Slm => PyTuneFamily::SLM,
+            TuneFamily::Bdw => PyTuneFamily::BDW,
+            TuneFamily::Skl => PyTuneFamily::SKL,
+            TuneFamily::Skx => PyTuneFamily::SKX,
+            TuneFamily::Glm => PyTuneFamily::GLM,
+            TuneFamily::Icl => PyTuneFamily::ICL,
+            TuneFamily::Icx => PyTuneFamily::ICX,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[pyclass(eq, name = "CpuFeature", module = "pyperscan._pyperscan")]
+#[derive(Clone, PartialEq)]
+enum PyCpuFeature {
+    AVX2,
+    AVX512,
+    AVX512VBMI,
+}
+
+impl From<&PyCpuFeature> for CpuFeatures {
+    fn from(feature: &PyCpuFeature) -> Self {
+        match feature {
+            PyCpuFeature::AVX2 => CpuFeatures::AVX2,
+            PyCpuFeature::AVX512 => CpuFeatures::AVX512,
+            PyCpuFeature::AVX512VBMI => CpuFeatures::AVX512VBMI,
+        }
+    }
+}
+
+#[pyclass(frozen, name = "Platform", module = "pyperscan._pyperscan")]
+struct PyPlatform(Platform);
+
+#[pymethods]
+impl PyPlatform {
+    #[new]
+    #[pyo3(signature = (*features, tune = PyTuneFamily::GENERIC))]
+    fn py_new(features: &Bound<'_, PyTuple>, tune: PyTuneFamily) -> PyResult<Self> {
+        let features = features
+            .iter()
+            .map(|f| f.extract::<PyCpuFeature>())
+            .collect::<PyResult<Vec<_>>>()?
+            .iter()
+            .fold(CpuFeatures::empty(), |a, f| a.union(f.into()));
+        Ok(Self(Platform::new(tune.into(), features)))
+    }
+
+    #[staticmethod]
+    fn host() -> PyResult<Self> {
+        Ok(Self(Platform::host()?))
+    }
+
+    #[getter]
+    fn tune(&self) -> PyTuneFamily {
+        self.0.tune().into()
+    }
+
+    #[getter]
+    fn features(&self) -> Vec<PyCpuFeature> {
+        let features = self.0.cpu_features();
+        [
+            PyCpuFeature::AVX2,
+            PyCpuFeature::AVX512,
+            PyCpuFeature::AVX512VBMI,
+        ]
+        .into_iter()
+        .filter(|f| features.contains(f.into()))
+        .collect()
+    }
+}
+
+/// Patterns of a database by their id, to resolve tags and `Match.pattern`.
+type PatternMapping = HashMap<u32, Py<PyPattern>>;
 
 struct PyContext {
     user_data: PyObject,
-    tag_mapping: TagMapping,
+    match_event_handler: PyObject,
+    /// Key of the `StreamTable` stream being scanned, passed to the handler.
+    flow_key: Option<PyObject>,
+    pattern_mapping: PatternMapping,
+    match_objects: bool,
+    data: ScanData,
+}
+
+impl PyContext {
+    fn traverse(&self, visit: &PyVisit<'_>) -> Result<(), PyTraverseError> {
+        visit.call(&self.user_data)?;
+        visit.call(&self.match_event_handler)?;
+        visit.call(&self.flow_key)?;
+        traverse_pattern_mapping(&self.pattern_mapping, visit)
+    }
+
+    /// Drops the Python objects referenced, to break reference cycles through them.
+    fn clear(&mut self, py: Python<'_>) {
+        self.user_data = py.None();
+        self.match_event_handler = py.None();
+        self.flow_key = None;
+        self.pattern_mapping.clear();
+    }
+
+    /// Context of a copied stream, continuing from the same stream offset.
+    fn clone_ref(&self, py: Python<'_>) -> Context<PyContext> {
+        let mut context = create_context(
+            py,
+            &self.pattern_mapping,
+            self.user_data.clone_ref(py),
+            self.match_event_handler.clone_ref(py),
+            self.match_objects,
+        );
+        context.user_data_mut().data.offset = self.data.offset;
+        context
+    }
+
+    fn to_tagged_matches(
+        &self,
+        py: Python<'_>,
+        matches: Vec<Match>,
+    ) -> PyResult<Vec<(PyObject, u64, u64)>> {
+        matches
+            .into_iter()
+            .map(|m| to_tagged_match(py, &self.pattern_mapping, m))
+            .collect()
+    }
+
+    fn to_match(&self, py: Python<'_>, id: u32, from: u64, to: u64) -> PyResult<PyMatch> {
+        // Databases restored from `hs_serialize_database` output have no patterns
+        let pattern = self.pattern_mapping.get(&id).map(|p| p.clone_ref(py));
+        let start = pattern
+            .as_ref()
+            .map_or(false, |p| p.get().flags.contains(Flag::SOM_LEFTMOST))
+            .then_some(from)
+            // Streams with a SOM horizon lose track of starts too far back
+            .filter(|&from| from != OFFSET_PAST_HORIZON && from <= to);
+        let data = start
+            .and_then(|start| self.data.slice(start, to))
+            .map(|data| PyBytes::new(py, &data).unbind());
+        let layout = self.data.layout.as_ref();
+        Ok(PyMatch {
+            tag: to_tag(py, &self.pattern_mapping, id)?,
+            pattern,
+            start,
+            end: to,
+            start_element: layout
+                .zip(start)
+                .and_then(|(layout, start)| layout.locate_start(start)),
+            end_element: layout.and_then(|layout| layout.locate_end(to)),
+            data,
+        })
+    }
+}
+
+/// Buffers of the scan in progress, for `Match.group()` to slice matched bytes from.
+#[derive(Default)]
+struct ScanData {
+    /// Offset of the buffers from the start of the stream.
+    offset: u64,
+    buffers: Vec<(*const u8, usize)>,
+    /// Element layout of a vectored scan.
+    layout: Option<VectorLayout>,
+}
+
+// Buffers are only referenced while the scan they belong to is in progress.
+unsafe impl Send for ScanData {}
+
+impl ScanData {
+    fn set(&mut self, buffers: &[&[u8]]) {
+        self.buffers = buffers.iter().map(|b| (b.as_ptr(), b.len())).collect();
+    }
+
+    fn set_vector(&mut self, buffers: &[&[u8]]) {
+        self.set(buffers);
+        self.layout = Some(VectorLayout::new(buffers));
+    }
+
+    fn clear(&mut self) {
+        self.buffers.clear();
+        self.layout = None;
+    }
+
+    /// Moves the stream offset past `len` scanned bytes.
+    fn advance(&mut self, len: usize) {
+        self.buffers.clear();
+        self.offset += len as u64;
+    }
+
+    fn slice(&self, from: u64, to: u64) -> Option<Vec<u8>> {
+        let mut from = usize::try_from(from.checked_sub(self.offset)?).ok()?;
+        let mut to = usize::try_from(to.checked_sub(self.offset)?).ok()?;
+        let mut data = Vec::with_capacity(to.checked_sub(from)?);
+        for &(ptr, len) in &self.buffers {
+            if to == 0 {
+                break;
+            }
+            if from < len {
+                let buffer = unsafe { slice::from_raw_parts(ptr, len) };
+                data.extend_from_slice(&buffer[from..to.min(len)]);
+            }
+            from = from.saturating_sub(len);
+            to = to.saturating_sub(len);
+        }
+        (to == 0).then_some(data)
+    }
+}
+
+#[pyclass(frozen, name = "Match", module = "pyperscan._pyperscan")]
+struct PyMatch {
+    #[pyo3(get)]
+    tag: PyObject,
+    #[pyo3(get)]
+    pattern: Option<Py<PyPattern>>,
+    #[pyo3(get)]
+    start: Option<u64>,
+    #[pyo3(get)]
+    end: u64,
+    #[pyo3(get)]
+    start_element: Option<ElementOffset>,
+    #[pyo3(get)]
+    end_element: Option<ElementOffset>,
+    data: Option<Py<PyBytes>>,
+}
+
+#[pymethods]
+impl PyMatch {
+    fn span(&self) -> (Option<u64>, u64) {
+        (self.start, self.end)
+    }
+
+    fn group(&self, py: Python<'_>) -> Option<Py<PyBytes>> {
+        self.data.as_ref().map(|data| data.clone_ref(py))
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let tag = self.tag.bind(py).repr()?;
+        let start = self
+            .start
+            .map_or("None".to_owned(), |start| start.to_string());
+        Ok(format!("<Match tag={tag} span=({start}, {})>", self.end))
+    }
+}
+
+fn traverse_pattern_mapping(
+    pattern_mapping: &PatternMapping,
+    visit: &PyVisit<'_>,
+) -> Result<(), PyTraverseError> {
+    pattern_mapping
+        .values()
+        .try_for_each(|pattern| visit.call(pattern))
+}
+
+fn to_tag(py: Python<'_>, pattern_mapping: &PatternMapping, id: u32) -> PyResult<PyObject> {
+    match pattern_mapping.get(&id).and_then(|p| p.get().tag.as_ref()) {
+        Some(tag) => Ok(tag.clone_ref(py)),
+        None => id.into_py_any(py),
+    }
+}
+
+fn to_tagged_match(
+    py: Python<'_>,
+    pattern_mapping: &PatternMapping,
+    (id, from, to): Match,
+) -> PyResult<(PyObject, u64, u64)> {
+    Ok((to_tag(py, pattern_mapping, id)?, from, to))
 }
 
 #[pyclass(name = "BlockDatabase", module = "pyperscan._pyperscan")]
 struct PyBlockDatabase {
     db: BlockDatabase,
-    tag_mapping: TagMapping,
+    pattern_mapping: PatternMapping,
 }
 
 #[pymethods]
 impl PyBlockDatabase {
     #[new]
-    #[pyo3(signature = (*patterns))]
-    fn py_new(py: Python<'_>, patterns: &Bound<'_, PyTuple>) -> PyResult<Self> {
-        let (patterns, tag_mapping) = to_tag_mapping(py, patterns)?;
+    #[pyo3(signature = (*patterns, platform = None, collect_errors = false))]
+    fn py_new(
+        py: Python<'_>,
+        patterns: &Bound<'_, PyTuple>,
+        platform: Option<PyRef<'_, PyPlatform>>,
+        collect_errors: bool,
+    ) -> PyResult<Self> {
+        let (hs_patterns, pattern_mapping) = to_pattern_mapping(patterns)?;
+        let platform = platform.map(|p| p.0);
+        let db = BlockDatabase::new(hs_patterns, platform.as_ref(), collect_errors)
+            .map_err(|err| to_compile_error(py, err, patterns))?;
         Ok(Self {
-            db: BlockDatabase::new(patterns)?,
-            tag_mapping,
+            db,
+            pattern_mapping,
         })
     }
 
+    #[pyo3(signature = (user_data, match_event_handler, match_objects = false))]
     fn build(
         &self,
         py: Python<'_>,
         user_data: PyObject,
         match_event_handler: PyObject,
+        match_objects: bool,
     ) -> PyResult<PyBlockScanner> {
-        let context = create_context(py, &self.tag_mapping, user_data, match_event_handler)?;
+        let context = create_context(
+            py,
+            &self.pattern_mapping,
+            user_data,
+            match_event_handler,
+            match_objects,
+        );
         let scanner = self.db.create_scanner(context)?;
         Ok(PyBlockScanner(scanner))
     }
+
+    #[pyo3(signature = (buffers, threads = None))]
+    fn scan_many(
+        &self,
+        py: Python<'_>,
+        buffers: Vec<Buffer>,
+        threads: Option<usize>,
+    ) -> PyResult<Vec<(usize, PyObject, u64, u64)>> {
+        let threads = match threads {
+            Some(0) => return Err(PyValueError::new_err("threads must be positive")),
+            Some(threads) => threads,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        let matches = py.allow_threads(|| {
+            let buffers = buffers.iter().map(|b| b.deref()).collect::<Vec<_>>();
+            self.db.scan_many(&buffers, threads)
+        })?;
+        matches
+            .into_iter()
+            .map(|(index, m)| {
+                let (tag, from, to) = to_tagged_match(py, &self.pattern_mapping, m)?;
+                Ok((index, tag, from, to))
+            })
+            .collect()
+    }
+
+    #[pyo3(signature = (user_data, match_event_handler, match_objects = false))]
+    fn build_pool(
+        &self,
+        py: Python<'_>,
+        user_data: PyObject,
+        match_event_handler: PyObject,
+        match_objects: bool,
+    ) -> PyResult<PyBlockScannerPool> {
+        Ok(PyBlockScannerPool {
+            pool: self.db.create_scanner_pool()?,
+            pattern_mapping: clone_pattern_mapping(py, &self.pattern_mapping),
+            user_data,
+            match_event_handler,
+            match_objects,
+        })
+    }
+
+    fn finditer(&self, py: Python<'_>, data: Buffer) -> PyResult<PyMatchIterator> {
+        Ok(PyMatchIterator {
+            matches: self.db.find_iter(data)?,
+            batch: Vec::new().into_iter(),
+            pattern_mapping: clone_pattern_mapping(py, &self.pattern_mapping),
+        })
+    }
+
+    #[getter]
+    fn info(&self) -> PyResult<String> {
+        Ok(self.db.info()?)
+    }
+
+    #[getter]
+    fn size(&self) -> PyResult<usize> {
+        Ok(self.db.size()?)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let data = dump_database(py, self.db.to_bytes()?, &self.pattern_mapping, true)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (data, tags = None))]
+    fn from_bytes(
+        py: Python<'_>,
+        data: Buffer,
+        tags: Option<HashMap<u32, PyObject>>,
+    ) -> PyResult<Self> {
+        let (bytes, pattern_mapping) = load_database(py, &data, tags)?;
+        Ok(Self {
+            db: BlockDatabase::from_bytes(bytes)?,
+            pattern_mapping,
+        })
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, ReducedDatabase)> {
+        let from_bytes = py.get_type::<Self>().getattr("from_bytes")?;
+        Ok((
+            from_bytes,
+            reduce_database(py, self.db.to_bytes()?, &self.pattern_mapping)?,
+        ))
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        traverse_pattern_mapping(&self.pattern_mapping, &visit)
+    }
+
+    fn __clear__(&mut self) {
+        self.pattern_mapping.clear();
+    }
+}
+
+#[pyclass(unsendable, name = "MatchIterator", module = "pyperscan._pyperscan")]
+struct PyMatchIterator {
+    matches: MatchIter,
+    batch: std::vec::IntoIter<Match>,
+    pattern_mapping: PatternMapping,
+}
+
+#[pymethods]
+impl PyMatchIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<(PyObject, u64, u64)>> {
+        loop {
+            if let Some(m) = self.batch.next() {
+                return to_tagged_match(py, &self.pattern_mapping, m).map(Some);
+            }
+            match py.allow_threads(|| self.matches.next_batch())? {
+                Some(batch) => self.batch = batch.into_iter(),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Terminates the scan and releases the scanned buffer.
+    fn close(&mut self, py: Python<'_>) {
+        self.batch = Vec::new().into_iter();
+        py.allow_threads(|| self.matches.cancel());
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        traverse_pattern_mapping(&self.pattern_mapping, &visit)
+    }
+
+    fn __clear__(&mut self) {
+        self.pattern_mapping.clear();
+    }
+}
+
+impl Drop for PyMatchIterator {
+    fn drop(&mut self) {
+        // The scanned buffer is released by the scanning thread
+        Python::with_gil(|py| py.allow_threads(|| self.matches.cancel()));
+    }
 }
 
 #[pyclass(unsendable, name = "BlockScanner", module = "pyperscan._pyperscan")]
@@ -140,39 +769,191 @@ struct PyBlockScanner(BlockScanner<PyContext>);
 
 #[pymethods]
 impl PyBlockScanner {
+    #[getter]
+    fn scratch_size(&self) -> PyResult<usize> {
+        Ok(self.0.scratch_size()?)
+    }
+
     fn scan(&mut self, py: Python, data: Buffer) -> PyResult<PyScan> {
-        py.allow_threads(|| Ok(self.0.scan(&data)?.into()))
+        self.0.user_data_mut().data.set(&[&data]);
+        let rv = py.allow_threads(|| self.0.scan(&data));
+        self.0.user_data_mut().data.clear();
+        Ok(rv?.into())
+    }
+
+    fn scan_file(&mut self, py: Python, path: PathBuf) -> PyResult<PyScan> {
+        with_mapped_file(py, path, |data| self.scan(py, data))
+    }
+
+    fn scan_collect(&mut self, py: Python, data: Buffer) -> PyResult<Vec<(PyObject, u64, u64)>> {
+        let matches = py.allow_threads(|| self.0.scan_collect(&data))?;
+        self.0.user_data().to_tagged_matches(py, matches)
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        self.0.user_data().traverse(&visit)
+    }
+
+    fn __clear__(&mut self, py: Python<'_>) {
+        self.0.user_data_mut().clear(py);
+    }
+}
+
+#[pyclass(name = "BlockScannerPool", module = "pyperscan._pyperscan")]
+struct PyBlockScannerPool {
+    pool: BlockScannerPool<PyContext>,
+    pattern_mapping: PatternMapping,
+    user_data: PyObject,
+    match_event_handler: PyObject,
+    match_objects: bool,
+}
+
+impl PyBlockScannerPool {
+    fn acquire(&self, py: Python<'_>) -> PyResult<BlockScanner<PyContext>> {
+        let scanner = self.pool.acquire(|| {
+            Ok(create_context(
+                py,
+                &self.pattern_mapping,
+                self.user_data.clone_ref(py),
+                self.match_event_handler.clone_ref(py),
+                self.match_objects,
+            ))
+        })?;
+        Ok(scanner)
+    }
+}
+
+#[pymethods]
+impl PyBlockScannerPool {
+    #[getter]
+    fn scratch_size(&self) -> PyResult<usize> {
+        Ok(self.pool.scratch_size()?)
+    }
+
+    fn scan(&self, py: Python, data: Buffer) -> PyResult<PyScan> {
+        let mut scanner = self.acquire(py)?;
+        scanner.user_data_mut().data.set(&[&data]);
+        let rv = py.allow_threads(|| scanner.scan(&data));
+        scanner.user_data_mut().data.clear();
+        self.pool.release(scanner);
+        Ok(rv?.into())
+    }
+
+    fn scan_collect(&self, py: Python, data: Buffer) -> PyResult<Vec<(PyObject, u64, u64)>> {
+        let mut scanner = self.acquire(py)?;
+        let matches = py.allow_threads(|| scanner.scan_collect(&data));
+        self.pool.release(scanner);
+        matches
+            .map_err(PyErr::from)?
+            .into_iter()
+            .map(|m| to_tagged_match(py, &self.pattern_mapping, m))
+            .collect()
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        visit.call(&self.user_data)?;
+        visit.call(&self.match_event_handler)?;
+        traverse_pattern_mapping(&self.pattern_mapping, &visit)?;
+        self.pool.try_for_each_idle(|ctx| ctx.traverse(&visit))
+    }
+
+    fn __clear__(&mut self, py: Python<'_>) {
+        self.pool.clear_idle();
+        self.user_data = py.None();
+        self.match_event_handler = py.None();
+        self.pattern_mapping.clear();
     }
 }
 
 #[pyclass(name = "VectoredDatabase", module = "pyperscan._pyperscan")]
 struct PyVectoredDatabase {
     db: VectoredDatabase,
-    tag_mapping: TagMapping,
+    pattern_mapping: PatternMapping,
 }
 
 #[pymethods]
 impl PyVectoredDatabase {
     #[new]
-    #[pyo3(signature = (*patterns))]
-    fn py_new(py: Python<'_>, patterns: &Bound<'_, PyTuple>) -> PyResult<Self> {
-        let (patterns, tag_mapping) = to_tag_mapping(py, patterns)?;
+    #[pyo3(signature = (*patterns, platform = None, collect_errors = false))]
+    fn py_new(
+        py: Python<'_>,
+        patterns: &Bound<'_, PyTuple>,
+        platform: Option<PyRef<'_, PyPlatform>>,
+        collect_errors: bool,
+    ) -> PyResult<Self> {
+        let (hs_patterns, pattern_mapping) = to_pattern_mapping(patterns)?;
+        let platform = platform.map(|p| p.0);
+        let db = VectoredDatabase::new(hs_patterns, platform.as_ref(), collect_errors)
+            .map_err(|err| to_compile_error(py, err, patterns))?;
         Ok(Self {
-            db: VectoredDatabase::new(patterns)?,
-            tag_mapping,
+            db,
+            pattern_mapping,
         })
     }
 
+    #[pyo3(signature = (user_data, match_event_handler, match_objects = false))]
     fn build(
         &self,
         py: Python<'_>,
         user_data: PyObject,
         match_event_handler: PyObject,
+        match_objects: bool,
     ) -> PyResult<PyVectoredScanner> {
-        let context = create_context(py, &self.tag_mapping, user_data, match_event_handler)?;
+        let context = create_context(
+            py,
+            &self.pattern_mapping,
+            user_data,
+            match_event_handler,
+            match_objects,
+        );
         let scanner = self.db.create_scanner(context)?;
         Ok(PyVectoredScanner(scanner))
     }
+
+    #[getter]
+    fn info(&self) -> PyResult<String> {
+        Ok(self.db.info()?)
+    }
+
+    #[getter]
+    fn size(&self) -> PyResult<usize> {
+        Ok(self.db.size()?)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let data = dump_database(py, self.db.to_bytes()?, &self.pattern_mapping, true)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (data, tags = None))]
+    fn from_bytes(
+        py: Python<'_>,
+        data: Buffer,
+        tags: Option<HashMap<u32, PyObject>>,
+    ) -> PyResult<Self> {
+        let (bytes, pattern_mapping) = load_database(py, &data, tags)?;
+        Ok(Self {
+            db: VectoredDatabase::from_bytes(bytes)?,
+            pattern_mapping,
+        })
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, ReducedDatabase)> {
+        let from_bytes = py.get_type::<Self>().getattr("from_bytes")?;
+        Ok((
+            from_bytes,
+            reduce_database(py, self.db.to_bytes()?, &self.pattern_mapping)?,
+        ))
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        traverse_pattern_mapping(&self.pattern_mapping, &visit)
+    }
+
+    fn __clear__(&mut self) {
+        self.pattern_mapping.clear();
+    }
 }
 
 #[pyclass(unsendable, name = "VectoredScanner", module = "pyperscan._pyperscan")]
@@ -180,41 +961,308 @@ struct PyVectoredScanner(VectoredScanner<PyContext>);
 
 #[pymethods]
 impl PyVectoredScanner {
+    #[getter]
+    fn scratch_size(&self) -> PyResult<usize> {
+        Ok(self.0.scratch_size()?)
+    }
+
     fn scan(&mut self, py: Python, data: Vec<Buffer>) -> PyResult<PyScan> {
-        py.allow_threads(|| {
+        let data: Vec<&[u8]> = data.iter().map(|d| d.deref()).collect();
+        self.0.user_data_mut().data.set_vector(&data);
+        let rv = py.allow_threads(|| self.0.scan(data));
+        self.0.user_data_mut().data.clear();
+        Ok(rv?.into())
+    }
+
+    fn scan_collect(
+        &mut self,
+        py: Python,
+        data: Vec<Buffer>,
+    ) -> PyResult<Vec<(PyObject, u64, u64)>> {
+        let matches = py.allow_threads(|| {
             let data = data.iter().map(|d| d.deref()).collect();
-            Ok(self.0.scan(data)?.into())
-        })
+            self.0.scan_collect(data)
+        })?;
+        self.0.user_data().to_tagged_matches(py, matches)
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        self.0.user_data().traverse(&visit)
+    }
+
+    fn __clear__(&mut self, py: Python<'_>) {
+        self.0.user_data_mut().clear(py);
     }
 }
 #[pyclass(name = "StreamDatabase", module = "pyperscan._pyperscan")]
 struct PyStreamDatabase {
     db: StreamDatabase,
-    tag_mapping: TagMapping,
+    pattern_mapping: PatternMapping,
 }
 
 #[pymethods]
 impl PyStreamDatabase {
     #[new]
-    #[pyo3(signature=(*patterns))]
-    fn py_new(py: Python<'_>, patterns: &Bound<'_, PyTuple>) -> PyResult<Self> {
-        let (patterns, tag_mapping) = to_tag_mapping(py, patterns)?;
+    #[pyo3(signature = (
+        *patterns,
+        som_horizon = None,
+        platform = None,
+        collect_errors = false,
+    ))]
+    fn py_new(
+        py: Python<'_>,
+        patterns: &Bound<'_, PyTuple>,
+        som_horizon: Option<&str>,
+        platform: Option<PyRef<'_, PyPlatform>>,
+        collect_errors: bool,
+    ) -> PyResult<Self> {
+        let som_horizon = som_horizon
+            .map(|horizon| match horizon {
+                "small" => Ok(SomHorizon::Small),
+                "medium" => Ok(SomHorizon::Medium),
+                "large" => Ok(SomHorizon::Large),
+                _ => Err(PyValueError::new_err(format!(
+                    "som_horizon must be one of 'small', 'medium' or 'large', not {horizon:?}"
+                ))),
+            })
+            .transpose()?;
+        let (hs_patterns, pattern_mapping) = to_pattern_mapping(patterns)?;
+        let platform = platform.map(|p| p.0);
+        let db = StreamDatabase::new(hs_patterns, som_horizon, platform.as_ref(), collect_errors)
+            .map_err(|err| to_compile_error(py, err, patterns))?;
         Ok(Self {
-            db: StreamDatabase::new(patterns)?,
-            tag_mapping,
+            db,
+            pattern_mapping,
         })
     }
 
+    #[pyo3(signature = (user_data, match_event_handler, match_objects = false))]
     fn build(
         &self,
         py: Python<'_>,
         user_data: PyObject,
         match_event_handler: PyObject,
+        match_objects: bool,
     ) -> PyResult<PyStreamScanner> {
-        let context = create_context(py, &self.tag_mapping, user_data, match_event_handler)?;
+        let context = create_context(
+            py,
+            &self.pattern_mapping,
+            user_data,
+            match_event_handler,
+            match_objects,
+        );
         let scanner = self.db.create_scanner(context)?;
         Ok(PyStreamScanner(scanner))
     }
+
+    #[pyo3(signature = (
+        user_data,
+        match_event_handler,
+        match_objects = false,
+        max_streams = None,
+    ))]
+    fn build_table(
+        &self,
+        py: Python<'_>,
+        user_data: PyObject,
+        match_event_handler: PyObject,
+        match_objects: bool,
+        max_streams: Option<usize>,
+    ) -> PyResult<PyStreamTable> {
+        if max_streams == Some(0) {
+            return Err(PyValueError::new_err("max_streams must be positive"));
+        }
+        let context = create_context(
+            py,
+            &self.pattern_mapping,
+            user_data,
+            match_event_handler,
+            match_objects,
+        );
+        Ok(PyStreamTable {
+            table: self.db.create_table(context)?,
+            ids: PyDict::new(py).unbind(),
+            flows: HashMap::new(),
+            next_id: 0,
+            max_streams,
+        })
+    }
+
+    #[getter]
+    fn info(&self) -> PyResult<String> {
+        Ok(self.db.info()?)
+    }
+
+    #[getter]
+    fn size(&self) -> PyResult<usize> {
+        Ok(self.db.size()?)
+    }
+
+    #[getter]
+    fn stream_size(&self) -> PyResult<usize> {
+        Ok(self.db.stream_size()?)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let data = dump_database(py, self.db.to_bytes()?, &self.pattern_mapping, true)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (data, tags = None))]
+    fn from_bytes(
+        py: Python<'_>,
+        data: Buffer,
+        tags: Option<HashMap<u32, PyObject>>,
+    ) -> PyResult<Self> {
+        let (bytes, pattern_mapping) = load_database(py, &data, tags)?;
+        Ok(Self {
+            db: StreamDatabase::from_bytes(bytes)?,
+            pattern_mapping,
+        })
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, ReducedDatabase)> {
+        let from_bytes = py.get_type::<Self>().getattr("from_bytes")?;
+        Ok((
+            from_bytes,
+            reduce_database(py, self.db.to_bytes()?, &self.pattern_mapping)?,
+        ))
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        traverse_pattern_mapping(&self.pattern_mapping, &visit)
+    }
+
+    fn __clear__(&mut self) {
+        self.pattern_mapping.clear();
+    }
+}
+
+#[pyclass(name = "StreamTable", module = "pyperscan._pyperscan", unsendable)]
+struct PyStreamTable {
+    table: StreamTable<PyContext>,
+    /// Stream ids by flow key.
+    ids: Py<PyDict>,
+    /// Flow key and stream offset by stream id.
+    flows: HashMap<u64, (PyObject, u64)>,
+    next_id: u64,
+    max_streams: Option<usize>,
+}
+
+impl PyStreamTable {
+    fn id(&self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<u64> {
+        match self.ids.bind(py).get_item(key)? {
+            Some(id) => id.extract(),
+            None => Err(PyKeyError::new_err(key.clone().unbind())),
+        }
+    }
+
+    fn flow(&self, id: u64) -> PyResult<&(PyObject, u64)> {
+        self.flows
+            .get(&id)
+            .ok_or_else(|| PyRuntimeError::new_err(format!("Unknown stream id {id}")))
+    }
+
+    fn close_id(&mut self, py: Python<'_>, id: u64) -> PyResult<PyScan> {
+        let (key, offset) = self.flow(id)?;
+        let (key, offset) = (key.clone_ref(py), *offset);
+        // Forgetting the key may raise from its `__hash__`, keep the stream open then
+        self.ids.bind(py).del_item(&key)?;
+        self.flows.remove(&id);
+        let ctx = self.table.user_data_mut();
+        ctx.flow_key = Some(key);
+        ctx.data.offset = offset;
+        let rv = self.table.close(id);
+        self.table.user_data_mut().flow_key = None;
+        Ok(rv?.into())
+    }
+}
+
+#[pymethods]
+impl PyStreamTable {
+    fn open(&mut self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        if self.ids.bind(py).contains(key)? {
+            return Err(PyValueError::new_err("Stream is already open"));
+        }
+        if self
+            .max_streams
+            .map_or(false, |max| self.table.len() >= max)
+        {
+            if let Some(id) = self.table.least_recently_used() {
+                self.close_id(py, id)?;
+            }
+        }
+        let id = self.next_id;
+        self.ids.bind(py).set_item(key, id)?;
+        if let Err(err) = self.table.open(id) {
+            self.ids.bind(py).del_item(key)?;
+            return Err(err.into());
+        }
+        self.next_id += 1;
+        self.flows.insert(id, (key.clone().unbind(), 0));
+        Ok(())
+    }
+
+    fn scan(&mut self, py: Python<'_>, key: &Bound<'_, PyAny>, data: Buffer) -> PyResult<PyScan> {
+        let id = self.id(py, key)?;
+        let offset = self.flow(id)?.1;
+        let ctx = self.table.user_data_mut();
+        ctx.flow_key = Some(key.clone().unbind());
+        ctx.data.offset = offset;
+        ctx.data.set(&[&data]);
+        let table = &mut self.table;
+        let rv = py.allow_threads(|| table.scan(id, &data));
+        let ctx = self.table.user_data_mut();
+        ctx.flow_key = None;
+        ctx.data.advance(data.len());
+        if let Some(flow) = self.flows.get_mut(&id) {
+            flow.1 = ctx.data.offset;
+        }
+        Ok(rv?.into())
+    }
+
+    fn close(&mut self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<PyScan> {
+        let id = self.id(py, key)?;
+        self.close_id(py, id)
+    }
+
+    fn evict_idle(&mut self, py: Python<'_>, seconds: f64) -> PyResult<usize> {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(PyValueError::new_err(
+                "seconds must be a non-negative number",
+            ));
+        }
+        let idle = self.table.idle(Duration::from_secs_f64(seconds));
+        // Close every idle stream even if some fail, reporting the first error
+        let mut result = Ok(idle.len());
+        for id in idle {
+            if let Err(err) = self.close_id(py, id) {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+
+    fn __len__(&self) -> usize {
+        self.table.len()
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        self.ids.bind(py).contains(key)
+    }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        self.table.user_data().traverse(&visit)?;
+        visit.call(&self.ids)?;
+        self.flows.values().try_for_each(|(key, _)| visit.call(key))
+    }
+
+    fn __clear__(&mut self, py: Python<'_>) {
+        self.table.user_data_mut().clear(py);
+        self.ids.bind(py).clear();
+        self.flows.clear();
+    }
 }
 
 #[pyclass(name = "StreamScanner", module = "pyperscan._pyperscan", unsendable)]
@@ -222,9 +1270,21 @@ struct PyStreamScanner(StreamScanner<PyContext>);
 
 #[pymethods]
 impl PyStreamScanner {
+    #[getter]
+    fn scratch_size(&self) -> PyResult<usize> {
+        Ok(self.0.scratch_size()?)
+    }
+
+    #[getter]
+    fn stream_size(&self) -> PyResult<usize> {
+        Ok(self.0.stream_size()?)
+    }
+
     #[pyo3(signature = (data, chunk_size = None))]
     fn scan(&mut self, py: Python, data: Buffer, chunk_size: Option<usize>) -> PyResult<PyScan> {
-        py.allow_threads(|| {
+        chunk_size.map(check_chunk_size).transpose()?;
+        self.0.user_data_mut().data.set(&[&data]);
+        let rv = py.allow_threads(|| {
             let mut rv = Scan::default();
             match chunk_size {
                 None => rv = self.0.scan(&data)?,
@@ -238,79 +1298,518 @@ impl PyStreamScanner {
                 }
             };
 
-            Ok(rv.into())
-        })
+            Ok::<_, Error>(rv)
+        });
+        self.0.user_data_mut().data.advance(data.len());
+        Ok(rv?.into())
+    }
+
+    #[pyo3(signature = (data, chunk_size = None))]
+    fn scan_collect(
+        &mut self,
+        py: Python,
+        data: Buffer,
+        chunk_size: Option<usize>,
+    ) -> PyResult<Vec<(PyObject, u64, u64)>> {
+        chunk_size.map(check_chunk_size).transpose()?;
+        let matches = py.allow_threads(|| match chunk_size {
+            None => self.0.scan_collect(&data),
+            Some(length) => {
+                let mut matches = Vec::new();
+                for slice in data.chunks(length) {
+                    matches.extend(self.0.scan_collect(slice)?);
+                }
+                Ok(matches)
+            }
+        });
+        self.0.user_data_mut().data.advance(data.len());
+        self.0.user_data().to_tagged_matches(py, matches?)
+    }
+
+    #[pyo3(signature = (reader, chunk_size = 65536))]
+    fn scan_reader(
+        &mut self,
+        py: Python,
+        reader: &Bound<'_, PyAny>,
+        chunk_size: usize,
+    ) -> PyResult<PyScan> {
+        check_chunk_size(chunk_size)?;
+        // Owned by Python, as `readinto` may keep references to it.
+        let buffer = PyByteArray::new_with(py, chunk_size, |_| Ok(()))?;
+        loop {
+            let read = reader
+                .call_method1("readinto", (&buffer,))?
+                .extract::<usize>()?;
+            if read == 0 {
+                return Ok(PyScan::Continue);
+            }
+            let data = buffer.extract::<Buffer>()?;
+            let data = &data[..read.min(data.len())];
+            self.0.user_data_mut().data.set(&[data]);
+            let rv = py.allow_threads(|| self.0.scan(data));
+            self.0.user_data_mut().data.advance(data.len());
+            if rv? == Scan::Terminate {
+                return Ok(PyScan::Terminate);
+            }
+        }
     }
 
     fn reset(&mut self) -> PyResult<PyScan> {
-        Ok(self.0.reset()?.into())
+        let rv = self.0.reset();
+        self.0.user_data_mut().data = ScanData::default();
+        Ok(rv?.into())
+    }
+
+    fn copy(&self, py: Python<'_>) -> PyResult<Self> {
+        let context = self.0.user_data().clone_ref(py);
+        Ok(Self(self.0.copy(context)?))
+    }
+
+    fn reset_from(slf: &Bound<'_, Self>, other: &Bound<'_, Self>) -> PyResult<PyScan> {
+        if slf.is(other) {
+            return Err(PyValueError::new_err("Cannot reset a stream from itself"));
+        }
+        let mut this = slf.borrow_mut();
+        let other = other.borrow();
+        let rv = this.0.reset_from(&other.0)?;
+        this.0.user_data_mut().data.offset = other.0.user_data().data.offset;
+        Ok(rv.into())
+    }
+
+    fn save_state<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let mut state = self.0.user_data().data.offset.to_le_bytes().to_vec();
+        state.extend(self.0.save_state()?);
+        Ok(PyBytes::new(py, &state))
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> PyResult<PyScan> {
+        let (offset, state) = state.split_at(state.len().min(8));
+        let offset = match <[u8; 8]>::try_from(offset) {
+            Ok(offset) => u64::from_le_bytes(offset),
+            Err(_) => return Err(Error::TruncatedState.into()),
+        };
+        let rv = self.0.restore_state(state)?;
+        self.0.user_data_mut().data.offset = offset;
+        Ok(rv.into())
+    }
+
+    fn close(&mut self) -> PyResult<PyScan> {
+        Ok(self.0.close()?.into())
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.0.is_closed()
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _exc_type: PyObject,
+        _exc_value: PyObject,
+        _traceback: PyObject,
+    ) -> PyResult<bool> {
+        self.close()?;
+        Ok(false)
     }
+
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
+        self.0.user_data().traverse(&visit)
+    }
+
+    fn __clear__(&mut self, py: Python<'_>) {
+        self.0.user_data_mut().clear(py);
+    }
+}
+
+fn check_chunk_size(chunk_size: usize) -> PyResult<()> {
+    if chunk_size == 0 {
+        return Err(PyValueError::new_err("chunk_size must be positive"));
+    }
+    Ok(())
 }
 
-fn to_tag_mapping(
+/// Calls `f` with the contents of the file at `path` mapped into memory.
+fn with_mapped_file<R>(
     py: Python<'_>,
-    patterns: &Bound<'_, PyTuple>,
-) -> PyResult<(Vec<Pattern>, TagMapping)> {
-    Ok(patterns
-        .into_iter()
-        .map(|p| p.extract::<Py<PyPattern>>())
-        .collect::<PyResult<Vec<_>>>()?
+    path: PathBuf,
+    f: impl FnOnce(Buffer) -> PyResult<R>,
+) -> PyResult<R> {
+    let file = py.import("builtins")?.call_method1("open", (path, "rb"))?;
+    let rv = (|| {
+        let fileno = file.call_method0("fileno")?;
+        let stat = py.import("os")?.call_method1("fstat", (&fileno,))?;
+        let regular = py
+            .import("stat")?
+            .call_method1("S_ISREG", (stat.getattr("st_mode")?,))?
+            .is_truthy()?;
+        if !regular || stat.getattr("st_size")?.extract::<u64>()? == 0 {
+            // Empty files cannot be mapped, and files like FIFOs or the ones in
+            // procfs report no size while having contents to read.
+            return f(file.call_method0("read")?.extract()?);
+        }
+        let mmap = py.import("mmap")?;
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("access", mmap.getattr("ACCESS_READ")?)?;
+        let mapped = mmap.getattr("mmap")?.call((fileno, 0), Some(&kwargs))?;
+        let rv = f(mapped.extract()?);
+        mapped.call_method0("close")?;
+        rv
+    })();
+    file.call_method0("close")?;
+    rv
+}
+
+fn to_pattern_mapping(patterns: &Bound<'_, PyTuple>) -> PyResult<(Vec<Pattern>, PatternMapping)> {
+    let patterns = patterns
         .iter()
+        .map(|p| Ok(p.downcast_into::<PyPattern>()?))
+        .collect::<PyResult<Vec<_>>>()?;
+    // Patterns without an id get their index, or the next id no other pattern uses.
+    let mut used_ids: HashSet<u32> = patterns.iter().filter_map(|p| p.get().id).collect();
+    let mut pattern_mapping = PatternMapping::new();
+    let patterns = patterns
+        .into_iter()
         .enumerate()
-        .map(move |(id, p)| {
-            let pat = p.borrow_mut(py);
-            let tag = pat.tag.as_ref().map(|t| t.clone_ref(py));
-            (
-                Pattern::new(
-                    pat.expression.clone(),
-                    pat.flags,
-                    Some(id.try_into().unwrap()),
-                ),
-                tag,
-            )
+        .map(|(index, pattern)| {
+            let pat = pattern.get();
+            let id = match pat.id {
+                Some(id) => id,
+                None => {
+                    let mut id = u32::try_from(index).unwrap();
+                    while !used_ids.insert(id) {
+                        id += 1;
+                    }
+                    id
+                }
+            };
+            pattern_mapping.insert(id, pattern.clone().unbind());
+            Ok(Pattern::new(
+                pat.expression.clone(),
+                pat.flags,
+                pat.ext,
+                pat.literal,
+                Some(id),
+            ))
         })
-        //.collect::<PyResult<(Pattern, Option<Arc<PyObject>>)>>()?
-        .unzip())
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok((patterns, pattern_mapping))
+}
+
+/// Prefix of databases serialized by `to_bytes`, followed by the length of the
+/// Hyperscan serialized database, the database itself and the patterns.
+const DATABASE_MAGIC: &[u8; 8] = b"PYPRSCN1";
+
+const TAG_NONE: u8 = 0;
+const TAG_STR: u8 = 1;
+const TAG_INT: u8 = 2;
+const TAG_BYTES: u8 = 3;
+
+/// Arguments of `from_bytes` to unpickle a database: tags are pickled as is, as the
+/// serialized form only supports `str`, `int` and `bytes` tags.
+type ReducedDatabase = (Py<PyBytes>, HashMap<u32, PyObject>);
+
+fn dump_database(
+    py: Python<'_>,
+    bytes: Vec<u8>,
+    pattern_mapping: &PatternMapping,
+    tags: bool,
+) -> PyResult<Vec<u8>> {
+    let mut out = DATABASE_MAGIC.to_vec();
+    write_bytes(&mut out, &bytes);
+    let mut ids: Vec<_> = pattern_mapping.keys().collect();
+    ids.sort_unstable();
+    out.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+    for id in ids {
+        out.extend_from_slice(&id.to_le_bytes());
+        pattern_mapping[id].get().write_to(py, &mut out, tags)?;
+    }
+    Ok(out)
+}
+
+fn reduce_database(
+    py: Python<'_>,
+    bytes: Vec<u8>,
+    pattern_mapping: &PatternMapping,
+) -> PyResult<ReducedDatabase> {
+    let data = dump_database(py, bytes, pattern_mapping, false)?;
+    let tags = pattern_mapping
+        .iter()
+        .filter_map(|(id, pattern)| Some((*id, pattern.get().tag.as_ref()?.clone_ref(py))))
+        .collect();
+    Ok((PyBytes::new(py, &data).unbind(), tags))
+}
+
+/// Splits a database serialized by `to_bytes` into the Hyperscan serialized database
+/// and its patterns.
+///
+/// Data without the `to_bytes` prefix is taken as the output of
+/// `hs_serialize_database`, with no patterns.
+fn split_database(data: &[u8]) -> PyResult<(&[u8], Option<DatabaseReader<'_>>)> {
+    match data.strip_prefix(DATABASE_MAGIC.as_slice()) {
+        Some(data) => {
+            let mut reader = DatabaseReader(data);
+            Ok((reader.bytes()?, Some(reader)))
+        }
+        None => Ok((data, None)),
+    }
+}
+
+fn load_database<'a>(
+    py: Python<'_>,
+    data: &'a [u8],
+    mut tags: Option<HashMap<u32, PyObject>>,
+) -> PyResult<(&'a [u8], PatternMapping)> {
+    let (bytes, reader) = split_database(data)?;
+    let mut pattern_mapping = PatternMapping::new();
+    if let Some(mut reader) = reader {
+        for _ in 0..reader.u32()? {
+            let id = reader.u32()?;
+            let mut pattern = PyPattern::read_from(py, &mut reader)?;
+            if let Some(tag) = tags.as_mut().and_then(|tags| tags.remove(&id)) {
+                pattern.tag = Some(tag);
+            }
+            pattern_mapping.insert(id, Py::new(py, pattern)?);
+        }
+        if !reader.0.is_empty() {
+            return Err(invalid_database());
+        }
+    }
+    // Tags are only stored on patterns, which raw Hyperscan databases have none of
+    if let Some(tags) = tags.filter(|tags| !tags.is_empty()) {
+        let mut ids: Vec<_> = tags.into_keys().collect();
+        ids.sort_unstable();
+        return Err(PyValueError::new_err(format!(
+            "Tags given for ids without a pattern in the database: {ids:?}"
+        )));
+    }
+    Ok((bytes, pattern_mapping))
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn write_option(out: &mut Vec<u8>, value: Option<u64>) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+fn write_tag(out: &mut Vec<u8>, tag: &Bound<'_, PyAny>) -> PyResult<()> {
+    if let Ok(tag) = tag.downcast_exact::<PyString>() {
+        out.push(TAG_STR);
+        write_bytes(out, tag.to_cow()?.as_bytes());
+    } else if let Ok(tag) = tag.downcast_exact::<PyInt>() {
+        out.push(TAG_INT);
+        write_bytes(out, tag.str()?.to_cow()?.as_bytes());
+    } else if let Ok(tag) = tag.downcast_exact::<PyBytes>() {
+        out.push(TAG_BYTES);
+        write_bytes(out, tag.as_bytes());
+    } else {
+        return Err(PyTypeError::new_err(format!(
+            "Cannot serialize tag of type {}, only str, int and bytes tags are \
+             supported. Pickle the database instead.",
+            tag.get_type().name()?
+        )));
+    }
+    Ok(())
+}
+
+fn invalid_database() -> PyErr {
+    PyValueError::new_err("Invalid serialized database")
+}
+
+/// Reads the patterns of a database serialized by `to_bytes`, failing on truncated
+/// or malformed data.
+struct DatabaseReader<'a>(&'a [u8]);
+
+impl<'a> DatabaseReader<'a> {
+    fn take(&mut self, len: usize) -> PyResult<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid_database());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> PyResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> PyResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> PyResult<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> PyResult<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_database()),
+        }
+    }
+
+    fn bytes(&mut self) -> PyResult<&'a [u8]> {
+        let len = usize::try_from(self.u64()?).map_err(|_| invalid_database())?;
+        self.take(len)
+    }
+
+    fn option_u64(&mut self) -> PyResult<Option<u64>> {
+        match self.bool()? {
+            true => self.u64().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn option_u32(&mut self) -> PyResult<Option<u32>> {
+        self.option_u64()?
+            .map(|value| u32::try_from(value).map_err(|_| invalid_database()))
+            .transpose()
+    }
+
+    fn str(&mut self) -> PyResult<&'a str> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| invalid_database())
+    }
+
+    fn tag(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let tag = match self.u8()? {
+            TAG_NONE => return Ok(None),
+            TAG_STR => self.str()?.into_py_any(py)?,
+            TAG_INT => {
+                let digits = self.str()?;
+                // Only accept what `str(int)` produces
+                let valid = digits.strip_prefix('-').unwrap_or(digits);
+                if valid.is_empty() || !valid.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid_database());
+                }
+                py.get_type::<PyInt>().call1((digits,))?.unbind()
+            }
+            TAG_BYTES => PyBytes::new(py, self.bytes()?).into_any().unbind(),
+            _ => return Err(invalid_database()),
+        };
+        Ok(Some(tag))
+    }
+}
+
+/// Converts compilation errors, resolving the tags of the offending patterns.
+fn to_compile_error(py: Python<'_>, err: Error, patterns: &Bound<'_, PyTuple>) -> PyErr {
+    let message = err.to_string();
+    match err {
+        Error::HyperscanCompileMany(errors) => {
+            let errors = errors
+                .into_iter()
+                .map(|(msg, index, expression)| {
+                    let pattern = patterns
+                        .get_item(index as usize)
+                        .and_then(|p| p.extract::<PyRef<'_, PyPattern>>())
+                        .ok();
+                    let tag = match pattern.as_deref() {
+                        Some(PyPattern { tag: Some(tag), .. }) => tag.clone_ref(py),
+                        Some(PyPattern { id: Some(id), .. }) => {
+                            PyInt::new(py, *id).into_any().unbind()
+                        }
+                        _ => PyInt::new(py, index).into_any().unbind(),
+                    };
+                    (index, tag, PyBytes::new(py, &expression).unbind(), msg)
+                })
+                .collect::<Vec<_>>();
+            let first = errors.first().map_or(-1, |(index, ..)| *index);
+            let err = HyperscanCompileErrors::new_err((message, first));
+            if let Err(e) = err.value(py).setattr("errors", errors) {
+                return e;
+            }
+            err
+        }
+        err => err.into(),
+    }
+}
+
+#[pyfunction]
+fn serialized_database_info(data: Buffer) -> PyResult<String> {
+    let (bytes, _) = split_database(&data)?;
+    Ok(hyperscan::serialized_database_info(bytes)?)
+}
+
+#[pyfunction]
+fn serialized_database_size(data: Buffer) -> PyResult<usize> {
+    let (bytes, _) = split_database(&data)?;
+    Ok(hyperscan::serialized_database_size(bytes)?)
+}
+
+fn clone_pattern_mapping(py: Python<'_>, pattern_mapping: &PatternMapping) -> PatternMapping {
+    pattern_mapping
+        .iter()
+        .map(|(id, pattern)| (*id, pattern.clone_ref(py)))
+        .collect()
 }
 
 fn create_context(
     py: Python<'_>,
-    tag_mapping: &TagMapping,
+    pattern_mapping: &PatternMapping,
     user_data: PyObject,
     match_event_handler: PyObject,
-) -> PyResult<Context<PyContext>> {
-    let match_handler = move |ctx: &mut PyContext, id, from, to| -> Result<Scan, Error> {
+    match_objects: bool,
+) -> Context<PyContext> {
+    let match_handler = |ctx: &mut PyContext, id, from, to| -> Result<Scan, Error> {
         Python::with_gil(|py| {
-            let result;
-            if let Some(id) = ctx.tag_mapping.get(id as usize).unwrap() {
-                let args = (&ctx.user_data, id, from, to);
-                result = match_event_handler.call1(py, args)?;
+            let handler = &ctx.match_event_handler;
+            let result = if ctx.match_objects {
+                let m = ctx.to_match(py, id, from, to)?;
+                match &ctx.flow_key {
+                    Some(key) => handler.call1(py, (&ctx.user_data, key, m))?,
+                    None => handler.call1(py, (&ctx.user_data, m))?,
+                }
             } else {
-                let args = (&ctx.user_data, id, from, to);
-                result = match_event_handler.call1(py, args)?;
-            }
+                let tag = to_tag(py, &ctx.pattern_mapping, id)?;
+                match &ctx.flow_key {
+                    Some(key) => handler.call1(py, (&ctx.user_data, key, tag, from, to))?,
+                    None => handler.call1(py, (&ctx.user_data, tag, from, to))?,
+                }
+            };
             result.extract::<PyScan>(py).map(|s| s.into())
         })
         .map_err(|exc| exc.into())
     };
 
-    let tag_mapping = tag_mapping
-        .iter()
-        .map(|ot| ot.as_ref().map(|t| t.clone_ref(py)))
-        .collect();
     let py_user_data = PyContext {
         user_data,
-        tag_mapping,
+        match_event_handler,
+        flow_key: None,
+        pattern_mapping: clone_pattern_mapping(py, pattern_mapping),
+        match_objects,
+        data: ScanData::default(),
     };
-    Ok(Context::new(py_user_data, match_handler))
+    Context::new(py_user_data, match_handler)
 }
 
 impl From<Error> for PyErr {
     fn from(err: Error) -> PyErr {
         match err {
-            Error::Nul(_) => PyValueError::new_err(format!("{err}")),
+            Error::Nul(_)
+            | Error::StreamClosed
+            | Error::DatabaseMismatch
+            | Error::TruncatedState
+            | Error::BufferTooLarge(_)
+            | Error::TooManyBuffers(_) => PyValueError::new_err(format!("{err}")),
             Error::Hyperscan(e, c) => HyperscanError::new_err((e, c)),
             Error::HyperscanCompile(msg, expr) => HyperscanCompileError::new_err((msg, expr)),
+            Error::HyperscanCompileMany(_) => {
+                Python::with_gil(|py| to_compile_error(py, err, &PyTuple::empty(py)))
+            }
+            Error::Panic(_) => PyRuntimeError::new_err(format!("{err}")),
             Error::Python(exc) => exc,
         }
     }
@@ -326,6 +1825,11 @@ create_exception!(
     HyperscanCompileError,
     pyo3::exceptions::PyException
 );
+create_exception!(
+    pyperscan._pyperscan,
+    HyperscanCompileErrors,
+    HyperscanCompileError
+);
 
 #[pymodule]
 fn _pyperscan(py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
@@ -333,17 +1837,51 @@ fn _pyperscan(py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyScan>()?;
     m.add_class::<PyBlockDatabase>()?;
     m.add_class::<PyBlockScanner>()?;
+    m.add_class::<PyBlockScannerPool>()?;
+    m.add_class::<PyMatchIterator>()?;
+    m.add_class::<PyMatch>()?;
     m.add_class::<PyVectoredDatabase>()?;
     m.add_class::<PyVectoredScanner>()?;
     m.add_class::<PyStreamDatabase>()?;
     m.add_class::<PyStreamScanner>()?;
+    m.add_class::<PyStreamTable>()?;
     m.add_class::<PyPattern>()?;
+    m.add_class::<PyExpressionInfo>()?;
+    m.add_class::<PyPlatform>()?;
+    m.add_class::<PyTuneFamily>()?;
+    m.add_class::<PyCpuFeature>()?;
     m.add_class::<HyperscanErrorCode>()?;
+    m.add_function(wrap_pyfunction!(serialized_database_info, m)?)?;
+    m.add_function(wrap_pyfunction!(serialized_database_size, m)?)?;
 
     m.add("HyperscanError", py.get_type::<HyperscanError>())?;
     m.add(
         "HyperscanCompileError",
         py.get_type::<HyperscanCompileError>(),
     )?;
+    m.add(
+        "HyperscanCompileErrors",
+        py.get_type::<HyperscanCompileErrors>(),
+    )?;
     Ok(())
 }
+
+// Needs an interpreter, which extension modules are not linked against
+#[cfg(all(test, not(feature = "extension-module")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_are_raised_as_runtime_errors() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let err = PyErr::from(Error::Panic("boom".to_owned()));
+
+            assert!(err.is_instance_of::<PyRuntimeError>(py));
+            assert_eq!(
+                err.value(py).to_string(),
+                "Match event handler panicked: boom"
+            );
+        });
+    }
+}
@@ -1,39 +1,59 @@
-use std::ffi::{c_int, c_void};
+use std::ffi::{c_char, c_int, c_void};
+use std::mem;
 use std::ops::Deref;
-use std::ptr;
 use std::slice;
 
-use pyo3::{ffi, prelude::*};
+use pyo3::{exceptions::PyBufferError, ffi, prelude::*};
 
-pub(crate) struct Buffer<'a>(&'a [u8]);
+/// Contiguous memory exported by a Python object through the buffer protocol.
+///
+/// The export is held until the buffer is dropped, which keeps the memory in place:
+/// e.g. a `bytearray` cannot be resized while it is exported.
+pub(crate) struct Buffer {
+    view: Box<Py_buffer>,
+}
+
+// The exporter keeps the memory alive and in place, and the data is only ever read.
+unsafe impl Send for Buffer {}
+unsafe impl Sync for Buffer {}
 
-impl Deref for Buffer<'_> {
+impl Deref for Buffer {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        self.0
+        if self.view.len == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.view.buf as *const u8, self.view.len as usize) }
+    }
+}
+
+impl FromPyObject<'_> for Buffer {
+    fn extract_bound(ob: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let mut view = Box::new(unsafe { mem::zeroed::<Py_buffer>() });
+        error_on_minus_one(ob.py(), unsafe {
+            PyObject_GetBuffer(ob.as_ptr(), &mut *view, PYBUF_STRIDES)
+        })?;
+        let buffer = Buffer { view };
+        if unsafe { PyBuffer_IsContiguous(&*buffer.view, b'C' as c_char) } == 0 {
+            return Err(PyBufferError::new_err(
+                "Buffer is not C-contiguous, scan a contiguous copy of it instead, \
+                 e.g. `bytes(data)`",
+            ));
+        }
+        Ok(buffer)
     }
 }
 
-impl<'a> FromPyObject<'a> for Buffer<'a> {
-    fn extract_bound(ob: &Bound<'a, PyAny>) -> PyResult<Self> {
-        let mut buf = ptr::null::<u8>();
-        let mut len = 0usize;
-        let buf = unsafe {
-            error_on_minus_one(
-                ob.py(),
-                PyObject_AsReadBuffer(
-                    ob.as_ptr(),
-                    &mut buf as *mut *const _ as *mut *const c_void,
-                    &mut len as *mut _ as *mut isize,
-                ),
-            )?;
-            slice::from_raw_parts(buf, len)
-        };
-        Ok(Buffer(buf))
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // Buffers scanned on background threads are released there.
+        Python::with_gil(|_| unsafe { PyBuffer_Release(&mut *self.view) });
     }
 }
 
+const PYBUF_STRIDES: c_int = 0x0018;
+
 #[inline]
 fn error_on_minus_one(py: Python, result: i32) -> PyResult<()> {
     if result == -1 {
@@ -43,10 +63,25 @@ fn error_on_minus_one(py: Python, result: i32) -> PyResult<()> {
     }
 }
 
+/// `Py_buffer` is not part of the limited API before Python 3.11.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct Py_buffer {
+    buf: *mut c_void,
+    obj: *mut ffi::PyObject,
+    len: ffi::Py_ssize_t,
+    itemsize: ffi::Py_ssize_t,
+    readonly: c_int,
+    ndim: c_int,
+    format: *mut c_char,
+    shape: *mut ffi::Py_ssize_t,
+    strides: *mut ffi::Py_ssize_t,
+    suboffsets: *mut ffi::Py_ssize_t,
+    internal: *mut c_void,
+}
+
 extern "C" {
-    fn PyObject_AsReadBuffer(
-        obj: *mut ffi::PyObject,
-        buffer: *mut *const c_void,
-        buffer_len: *mut ffi::Py_ssize_t,
-    ) -> c_int;
+    fn PyObject_GetBuffer(obj: *mut ffi::PyObject, view: *mut Py_buffer, flags: c_int) -> c_int;
+    fn PyBuffer_Release(view: *mut Py_buffer);
+    fn PyBuffer_IsContiguous(view: *const Py_buffer, order: c_char) -> c_int;
 }
@@ -1,8 +1,21 @@
+import gc
+import io
 import mmap
+import os
+import pickle
+import sys
+import weakref
+from concurrent.futures import ThreadPoolExecutor
 from unittest import mock
 
 import pyperscan as ps
 import pytest
+from pyperscan._pyperscan import (
+    HyperscanCompileError,
+    HyperscanCompileErrors,
+    HyperscanError,
+    HyperscanErrorCode,
+)
 
 
 def args(*args, **kwargs):
@@ -26,6 +39,14 @@ def args(*args, **kwargs):
             args(b"foo", ps.Flag.SOM_LEFTMOST, ps.Flag.DOTALL, tag="bar"),
             id="flag-tag",
         ),
+        pytest.param(
+            args(b"foo", min_offset=1, max_offset=10, min_length=3),
+            id="extended-parameters",
+        ),
+        pytest.param(args(b"foo", edit_distance=1), id="edit-distance"),
+        pytest.param(args(b"foo", hamming_distance=1), id="hamming-distance"),
+        pytest.param(args(b"f\x00o", literal=True), id="literal"),
+        pytest.param(args(b"foo", id=1234), id="id"),
     ],
 )
 def test_patterns(args):
@@ -186,6 +207,32 @@ def test_data_can_be_mmap_object(ctx, on_match):
         on_match.assert_called_with(ctx, 0, 0, 3)
 
 
+def test_data_can_be_multi_dimensional(ctx, on_match):
+    scan = ps.BlockDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    scan.scan(memoryview(b"xxfoox").cast("B", (2, 3)))
+    on_match.assert_called_with(ctx, 0, 0, 5)
+
+
+def test_data_must_be_contiguous(ctx, on_match):
+    scan = ps.BlockDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    with pytest.raises(BufferError, match="not C-contiguous"):
+        scan.scan(memoryview(b"fxoxo")[::2])
+    on_match.assert_not_called()
+
+
+def test_data_cannot_be_resized_while_scanned(ctx, on_match):
+    scan = ps.BlockDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+    data = bytearray(b"foo")
+    on_match.side_effect = lambda *_: data.extend(b"x" * 4096)
+
+    with pytest.raises(BufferError):
+        scan.scan(data)
+    assert data == b"foo"
+    data.extend(b"bar")
+
+
 @pytest.mark.parametrize(
     "database,data",
     [
@@ -209,3 +256,961 @@ def test_scanning_can_be_aborted(database, data, ctx, on_match):
     on_match.return_value = ps.Scan.Terminate
     assert scan.scan(data) == ps.Scan.Terminate
     assert on_match.call_count == 1
+
+
+@pytest.mark.parametrize(
+    "database,data",
+    [
+        (ps.BlockDatabase, b"barfoo"),
+        (ps.VectoredDatabase, (b"bar", b"foo")),
+        (ps.StreamDatabase, b"barfoo"),
+    ],
+    indirect=("database",),
+)
+def test_database_serialization(database, data, ctx, tag, on_match):
+    restored = type(database).from_bytes(database.to_bytes())
+
+    restored.build(ctx, on_match).scan(data)
+    on_match.assert_called_once_with(ctx, tag, 3, 6)
+
+
+@pytest.mark.parametrize(
+    "database,data",
+    [
+        (ps.BlockDatabase, b"foo"),
+        (ps.VectoredDatabase, (b"foo",)),
+        (ps.StreamDatabase, b"foo"),
+    ],
+    indirect=("database",),
+)
+def test_database_can_be_pickled(database, data, ctx, tag, on_match):
+    restored = pickle.loads(pickle.dumps(database))  # noqa: S301
+
+    assert type(restored) is type(database)
+    restored.build(ctx, on_match).scan(data)
+    on_match.assert_called_once_with(ctx, tag, 0, 3)
+
+
+@pytest.mark.parametrize(
+    "source,target",
+    [
+        (ps.BlockDatabase, ps.StreamDatabase),
+        (ps.StreamDatabase, ps.VectoredDatabase),
+        (ps.VectoredDatabase, ps.BlockDatabase),
+    ],
+)
+def test_database_deserialization_checks_mode(source, target):
+    data = source(ps.Pattern(b"foo")).to_bytes()
+
+    with pytest.raises(HyperscanError) as exc_info:
+        target.from_bytes(data)
+    assert exc_info.value.args[0] == HyperscanErrorCode.DbModeError
+
+
+EXPLOITED = []
+
+
+def exploit():
+    EXPLOITED.append(True)
+
+
+class Exploit:
+    def __reduce__(self):
+        return (exploit, ())
+
+
+def test_database_deserialization_does_not_unpickle():
+    with pytest.raises(HyperscanError):
+        ps.BlockDatabase.from_bytes(pickle.dumps(Exploit()))
+    assert not EXPLOITED
+
+
+def test_database_deserialization_rejects_malformed_data():
+    data = ps.BlockDatabase(ps.Pattern(b"foo", tag="foo")).to_bytes()
+
+    with pytest.raises(ValueError, match="Invalid serialized database"):
+        ps.BlockDatabase.from_bytes(data[:-1])
+    with pytest.raises(ValueError, match="Invalid serialized database"):
+        ps.BlockDatabase.from_bytes(data + b"\0")
+    # Unknown kind of the tag, serialized as its kind, length and "foo"
+    tampered = data[:-12] + b"\x09" + data[-11:]
+    with pytest.raises(ValueError, match="Invalid serialized database"):
+        ps.BlockDatabase.from_bytes(tampered)
+
+
+def test_database_deserialization_accepts_hyperscan_format(ctx, on_match):
+    data = ps.BlockDatabase(ps.Pattern(b"foo", tag="foo")).to_bytes()
+    # Strip the header and patterns around the Hyperscan serialized database.
+    length = int.from_bytes(data[8:16], "little")
+
+    db = ps.BlockDatabase.from_bytes(data[16 : 16 + length])
+    db.build(ctx, on_match).scan(b"foo")
+    on_match.assert_called_once_with(ctx, 0, 0, 3)
+
+
+def test_match_objects_of_database_without_patterns(ctx, on_match):
+    data = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST)).to_bytes()
+    length = int.from_bytes(data[8:16], "little")
+    db = ps.BlockDatabase.from_bytes(data[16 : 16 + length])
+
+    db.build(ctx, on_match, match_objects=True).scan(b"foo")
+    match = on_match.call_args.args[1]
+    assert (match.tag, match.pattern, match.span()) == (0, None, (None, 3))
+
+
+@pytest.mark.parametrize("tag", ["foo", -(2**70), b"foo", None])
+def test_database_serialization_keeps_tags(tag, ctx, on_match):
+    data = ps.BlockDatabase(ps.Pattern(b"foo", tag=tag)).to_bytes()
+
+    ps.BlockDatabase.from_bytes(data).build(ctx, on_match).scan(b"foo")
+    on_match.assert_called_once_with(ctx, 0 if tag is None else tag, 0, 3)
+
+
+def test_database_serialization_rejects_arbitrary_tags(ctx, on_match):
+    tag = ("foo", 1)
+    db = ps.BlockDatabase(ps.Pattern(b"foo", tag=tag))
+
+    with pytest.raises(TypeError, match="tuple"):
+        db.to_bytes()
+    pickle.loads(pickle.dumps(db)).build(ctx, on_match).scan(b"foo")  # noqa: S301
+    on_match.assert_called_once_with(ctx, tag, 0, 3)
+
+
+def test_database_deserialization_replaces_tags(ctx, on_match):
+    data = ps.BlockDatabase(ps.Pattern(b"foo", tag="foo")).to_bytes()
+
+    db = ps.BlockDatabase.from_bytes(data, tags={0: ("bar", 1)})
+    db.build(ctx, on_match).scan(b"foo")
+    on_match.assert_called_once_with(ctx, ("bar", 1), 0, 3)
+
+
+def test_database_deserialization_rejects_tags_of_unknown_ids():
+    data = ps.BlockDatabase(ps.Pattern(b"foo", tag="foo")).to_bytes()
+
+    with pytest.raises(ValueError, match=r"\[1, 2\]"):
+        ps.BlockDatabase.from_bytes(data, tags={0: "foo", 2: "bar", 1: "baz"})
+
+
+def test_database_deserialization_rejects_tags_without_patterns():
+    data = ps.BlockDatabase(ps.Pattern(b"foo")).to_bytes()
+    length = int.from_bytes(data[8:16], "little")
+
+    with pytest.raises(ValueError, match=r"\[0\]"):
+        ps.BlockDatabase.from_bytes(data[16 : 16 + length], tags={0: "foo"})
+
+
+@pytest.mark.parametrize(
+    "kwargs,expected",
+    [
+        pytest.param({}, [(0, 3), (4, 7), (8, 11)], id="unbounded"),
+        pytest.param({"min_offset": 7}, [(4, 7), (8, 11)], id="min-offset"),
+        pytest.param({"max_offset": 7}, [(0, 3), (4, 7)], id="max-offset"),
+        pytest.param({"min_length": 4}, [], id="min-length"),
+    ],
+)
+def test_pattern_extended_parameters(kwargs, expected, ctx, on_match):
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="tag", **kwargs))
+    db.build(ctx, on_match).scan(b"foo foo foo")
+
+    assert on_match.call_args_list == [
+        mock.call(ctx, "tag", start, end) for start, end in expected
+    ]
+
+
+def test_pattern_extended_parameters_are_validated():
+    with pytest.raises(HyperscanCompileError):
+        ps.BlockDatabase(ps.Pattern(b"foo", min_offset=10, max_offset=5))
+
+
+@pytest.mark.parametrize(
+    "kwargs,data",
+    [
+        pytest.param({"edit_distance": 1}, b"fooo", id="edit-distance"),
+        pytest.param({"hamming_distance": 1}, b"fxo", id="hamming-distance"),
+    ],
+)
+def test_pattern_approximate_matching(kwargs, data, ctx, on_match):
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SINGLEMATCH, **kwargs))
+    db.build(ctx, on_match).scan(data)
+
+    on_match.assert_called_once()
+
+
+@pytest.mark.parametrize(
+    "flags,kwargs",
+    [
+        pytest.param(
+            (ps.Flag.SOM_LEFTMOST,), {"edit_distance": 1}, id="edit-distance-som"
+        ),
+        pytest.param(
+            (ps.Flag.SOM_LEFTMOST,), {"hamming_distance": 1}, id="hamming-distance-som"
+        ),
+        pytest.param((), {"edit_distance": 1, "hamming_distance": 1}, id="both"),
+    ],
+)
+def test_pattern_approximate_matching_is_validated(flags, kwargs):
+    with pytest.raises(HyperscanCompileError) as exc_info:
+        ps.BlockDatabase(ps.Pattern(b"bar"), ps.Pattern(b"foo", *flags, **kwargs))
+    assert exc_info.value.args[1] == 1
+
+
+def test_literal_patterns(ctx, on_match):
+    db = ps.BlockDatabase(
+        ps.Pattern(b"a.b", literal=True, tag="dot"),
+        ps.Pattern(b"\x00\xff", ps.Flag.SOM_LEFTMOST, literal=True, tag="binary"),
+    )
+    db.build(ctx, on_match).scan(b"axb a.b \x00\xff")
+
+    assert on_match.call_args_list == [
+        mock.call(ctx, "dot", 0, 7),
+        mock.call(ctx, "binary", 8, 10),
+    ]
+
+
+@pytest.mark.parametrize(
+    "pattern",
+    [
+        pytest.param(ps.Pattern(b"foo", ps.Flag.DOTALL, literal=True), id="flags"),
+        pytest.param(ps.Pattern(b"foo", literal=True, min_offset=1), id="ext"),
+        pytest.param(ps.Pattern(b"foo"), id="mixed"),
+    ],
+)
+def test_literal_pattern_restrictions(pattern):
+    with pytest.raises(HyperscanCompileError) as exc_info:
+        ps.BlockDatabase(ps.Pattern(b"bar", literal=True), pattern)
+    assert exc_info.value.args[1] == 1
+
+
+@pytest.mark.parametrize(
+    "database,mode",
+    [
+        (ps.BlockDatabase, "BLOCK"),
+        (ps.VectoredDatabase, "VECTORED"),
+        (ps.StreamDatabase, "STREAM"),
+    ],
+    indirect=("database",),
+)
+def test_database_introspection(database, mode, ctx, on_match):
+    assert database.info.endswith(f"Mode: {mode}")
+    assert database.size > 0
+    assert database.build(ctx, on_match).scratch_size > 0
+
+    data = database.to_bytes()
+    assert ps.serialized_database_info(data) == database.info
+    assert ps.serialized_database_size(data) == database.size
+
+
+def test_serialized_database_info_reads_only_hyperscan_data():
+    db = ps.BlockDatabase(ps.Pattern(b"foo", tag="foo"))
+    data = db.to_bytes()
+    length = int.from_bytes(data[8:16], "little")
+
+    # Patterns are not read, truncating them does not matter
+    assert ps.serialized_database_info(data[:-1]) == db.info
+    assert ps.serialized_database_info(data[16 : 16 + length]) == db.info
+    assert ps.serialized_database_size(data[16 : 16 + length]) == db.size
+
+
+@pytest.mark.parametrize("database", [ps.StreamDatabase], indirect=True)
+def test_stream_size(database, ctx, on_match):
+    assert database.stream_size > 0
+    assert database.build(ctx, on_match).stream_size == database.stream_size
+
+
+@pytest.mark.parametrize(
+    "pattern,expected",
+    [
+        pytest.param(
+            ps.Pattern(b"fo+"), (2, None, False, False, False), id="unbounded"
+        ),
+        pytest.param(ps.Pattern(b"foo$"), (3, 3, False, True, True), id="eod"),
+        pytest.param(
+            ps.Pattern(b"foo", literal=True), (3, 3, False, False, False), id="literal"
+        ),
+    ],
+)
+def test_pattern_info(pattern, expected):
+    info = pattern.info()
+
+    assert (
+        info.min_width,
+        info.max_width,
+        info.unordered_matches,
+        info.matches_at_eod,
+        info.matches_only_at_eod,
+    ) == expected
+
+
+def test_pattern_info_reports_compile_error():
+    with pytest.raises(HyperscanCompileError, match="Missing close parenthesis"):
+        ps.Pattern(b"foo(").info()
+
+
+@pytest.mark.parametrize(
+    "database_cls", [ps.BlockDatabase, ps.VectoredDatabase, ps.StreamDatabase]
+)
+def test_collect_compile_errors(database_cls):
+    with pytest.raises(HyperscanCompileErrors) as exc_info:
+        database_cls(
+            ps.Pattern(b"foo("),
+            ps.Pattern(b"bar"),
+            ps.Pattern(b"baz[", tag="tag"),
+            collect_errors=True,
+        )
+
+    assert exc_info.value.args[1] == 0
+    errors = exc_info.value.errors
+    assert [(index, tag, expression) for index, tag, expression, _ in errors] == [
+        (0, 0, b"foo("),
+        (2, "tag", b"baz["),
+    ]
+    assert all(message for *_, message in errors)
+
+
+def test_collect_compile_errors_of_combinations():
+    with pytest.raises(HyperscanCompileErrors) as exc_info:
+        ps.BlockDatabase(
+            ps.Pattern(b"foo", id=1),
+            ps.Pattern(b"1 & 3", ps.Flag.COMBINATION, tag="tag"),
+            collect_errors=True,
+        )
+
+    assert exc_info.value.args[1] == 1
+    [(index, tag, expression, message)] = exc_info.value.errors
+    assert (index, tag, expression) == (1, "tag", b"1 & 3")
+    assert message
+
+
+def test_compile_errors_are_not_collected_by_default():
+    with pytest.raises(HyperscanCompileError) as exc_info:
+        ps.BlockDatabase(ps.Pattern(b"foo("), ps.Pattern(b"baz["))
+
+    assert not isinstance(exc_info.value, HyperscanCompileErrors)
+    assert exc_info.value.args[1] == 0
+
+
+def test_platform():
+    platform = ps.Platform(ps.CpuFeature.AVX2, tune=ps.TuneFamily.HSW)
+
+    assert platform.tune == ps.TuneFamily.HSW
+    assert platform.features == [ps.CpuFeature.AVX2]
+
+
+def test_host_platform():
+    platform = ps.Platform.host()
+
+    assert isinstance(platform.tune, ps.TuneFamily)
+
+
+@pytest.mark.parametrize(
+    "database_cls", [ps.BlockDatabase, ps.VectoredDatabase, ps.StreamDatabase]
+)
+@pytest.mark.parametrize(
+    "platform", [ps.Platform(), ps.Platform.host()], ids=["generic", "host"]
+)
+def test_database_platform(database_cls, platform):
+    db = database_cls(ps.Pattern(b"foo"), platform=platform)
+
+    restored = database_cls.from_bytes(db.to_bytes())
+    assert restored.info == db.info
+
+
+@pytest.mark.parametrize("som_horizon", [None, "small", "medium", "large"])
+def test_stream_database_som_horizon(som_horizon, ctx, on_match):
+    db = ps.StreamDatabase(
+        ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST), som_horizon=som_horizon
+    )
+    scan = db.build(ctx, on_match)
+
+    scan.scan(b"barf")
+    scan.scan(b"oo")
+    on_match.assert_called_once_with(ctx, 0, 3, 6)
+
+
+def test_stream_database_without_som_needs_no_horizon():
+    without_som = ps.StreamDatabase(ps.Pattern(b"fo+bar"))
+    with_som = ps.StreamDatabase(ps.Pattern(b"fo+bar", ps.Flag.SOM_LEFTMOST))
+
+    assert without_som.stream_size < with_som.stream_size
+
+
+def test_stream_database_som_horizon_is_validated():
+    with pytest.raises(ValueError, match="som_horizon"):
+        ps.StreamDatabase(ps.Pattern(b"foo"), som_horizon="tiny")  # type: ignore
+
+
+def test_pattern_ids(ctx, on_match):
+    db = ps.BlockDatabase(
+        ps.Pattern(b"foo", ps.Flag.QUIET, id=101),
+        ps.Pattern(b"bar", ps.Flag.QUIET, id=102),
+        ps.Pattern(b"baz", id=103),
+        ps.Pattern(b"101&102", ps.Flag.COMBINATION, tag="foobar"),
+    )
+    scan = db.build(ctx, on_match)
+
+    scan.scan(b"foo bar")
+    on_match.assert_called_once_with(ctx, "foobar", 0, 7)
+    scan.scan(b"baz")
+    on_match.assert_called_with(ctx, 103, 0, 3)
+
+
+def test_duplicate_pattern_ids_are_rejected():
+    with pytest.raises(HyperscanCompileError, match="duplicate pattern id 0") as exc:
+        ps.BlockDatabase(ps.Pattern(b"foo", id=0), ps.Pattern(b"bar", id=0))
+    assert exc.value.args[1] == 1
+
+
+def test_implicit_pattern_ids_avoid_explicit_ones(ctx, on_match):
+    db = ps.BlockDatabase(
+        ps.Pattern(b"foo"),
+        ps.Pattern(b"bar", id=0),
+        ps.Pattern(b"baz"),
+        ps.Pattern(b"qux", id=1),
+    )
+    scan = db.build(ctx, on_match)
+
+    for data, pattern_id in [(b"foo", 2), (b"bar", 0), (b"baz", 3), (b"qux", 1)]:
+        scan.scan(data)
+        on_match.assert_called_with(ctx, pattern_id, 0, 3)
+
+
+def test_scan_collect(ctx, on_match):
+    db = ps.BlockDatabase(
+        ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="foo"),
+        ps.Pattern(b"bar", ps.Flag.SOM_LEFTMOST),
+    )
+    scan = db.build(ctx, on_match)
+
+    assert scan.scan_collect(b"foobarfoo") == [("foo", 0, 3), (1, 3, 6), ("foo", 6, 9)]
+    on_match.assert_not_called()
+
+
+@pytest.mark.parametrize("database", [ps.VectoredDatabase], indirect=True)
+def test_vectored_scan_collect(database, ctx, tag, on_match):
+    scan = database.build(ctx, on_match)
+
+    assert scan.scan_collect([b"foo", b"barfoo"]) == [(tag, 0, 3), (tag, 6, 9)]
+    on_match.assert_not_called()
+
+
+@pytest.mark.parametrize("database", [ps.StreamDatabase], indirect=True)
+def test_stream_scan_collect(database, ctx, tag, on_match):
+    scan = database.build(ctx, on_match)
+
+    assert scan.scan_collect(b"foobarf", chunk_size=4) == [(tag, 0, 3)]
+    assert scan.scan_collect(b"oo") == [(tag, 6, 9)]
+    on_match.assert_not_called()
+
+
+def test_finditer():
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="foo"))
+
+    matches = db.finditer(b"foo" * 1000)
+    assert next(matches) == ("foo", 0, 3)
+    assert list(matches) == [("foo", i, i + 3) for i in range(3, 3000, 3)]
+
+
+def test_finditer_can_be_abandoned():
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST))
+
+    for tag, start, end in db.finditer(b"foo" * 100_000):
+        assert (tag, start, end) == (0, 0, 3)
+        break
+
+
+def test_finditer_releases_buffer_when_abandoned():
+    db = ps.BlockDatabase(ps.Pattern(b"foo"))
+    data = bytearray(b"foo" * 100_000)
+
+    for _ in db.finditer(data):
+        break
+    # Resizing fails with BufferError while the scan still holds the buffer
+    data.extend(b"bar")
+
+
+def test_finditer_can_be_closed():
+    db = ps.BlockDatabase(ps.Pattern(b"foo"))
+    data = bytearray(b"foo" * 100_000)
+
+    matches = db.finditer(data)
+    assert next(matches) == (0, 0, 3)
+    matches.close()
+    data.extend(b"bar")
+    assert list(matches) == []
+
+
+def test_match_objects(ctx, on_match):
+    foo = ps.Pattern(b"fo+", ps.Flag.SOM_LEFTMOST, tag="foo")
+    bar = ps.Pattern(b"bar")
+    scan = ps.BlockDatabase(foo, bar).build(ctx, on_match, match_objects=True)
+
+    scan.scan(b"xfoobar")
+    (_, foo_match), (_, bar_match) = (c.args for c in on_match.call_args_list)
+    assert foo_match.tag == "foo"
+    assert foo_match.pattern is foo
+    assert foo_match.span() == (1, 4)
+    assert foo_match.group() == b"foo"
+    assert bar_match.tag == 1
+    assert bar_match.pattern.expression == b"bar"
+    assert bar_match.span() == (None, 7)
+    assert bar_match.group() is None
+
+
+def test_vectored_match_objects(ctx, on_match):
+    db = ps.VectoredDatabase(ps.Pattern(b"foobar", ps.Flag.SOM_LEFTMOST))
+    scan = db.build(ctx, on_match, match_objects=True)
+
+    scan.scan([b"xfoo", b"ba", b"r"])
+    [(_, match)] = (c.args for c in on_match.call_args_list)
+    assert match.span() == (1, 7)
+    assert match.group() == b"foobar"
+
+
+def test_vectored_match_elements(ctx, on_match):
+    foo = ps.Pattern(b"foobar", ps.Flag.SOM_LEFTMOST)
+    bar = ps.Pattern(b"bar")
+    scan = ps.VectoredDatabase(foo, bar).build(ctx, on_match, match_objects=True)
+
+    scan.scan([b"xfoo", b"", b"ba", b"rx"])
+    matches = {c.args[1].pattern.expression: c.args[1] for c in on_match.call_args_list}
+    foo_match, bar_match = matches[b"foobar"], matches[b"bar"]
+    assert foo_match.start_element == (0, 1)
+    assert foo_match.end_element == (3, 1)
+    assert bar_match.start_element is None
+    assert bar_match.end_element == (3, 1)
+
+    on_match.reset_mock()
+    scan.scan([b"foobar"])
+    assert on_match.call_args.args[1].start_element == (0, 0)
+    assert on_match.call_args.args[1].end_element == (0, 6)
+
+
+def test_match_elements_not_vectored(ctx, on_match):
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST))
+    db.build(ctx, on_match, match_objects=True).scan(b"foo")
+    assert on_match.call_args.args[1].start_element is None
+    assert on_match.call_args.args[1].end_element is None
+
+
+def test_stream_match_objects(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST))
+    scan = db.build(ctx, on_match, match_objects=True)
+
+    scan.scan(b"barfoo")
+    assert on_match.call_args.args[1].group() == b"foo"
+    scan.scan(b"f")
+    scan.scan(b"oo")
+    assert on_match.call_args.args[1].span() == (6, 9)
+    assert on_match.call_args.args[1].group() is None
+    scan.scan(b"xfoo", chunk_size=2)
+    assert on_match.call_args.args[1].span() == (10, 13)
+    assert on_match.call_args.args[1].group() == b"foo"
+
+
+def test_stream_match_objects_past_som_horizon(ctx, on_match):
+    pattern = ps.Pattern(b"a.*b", ps.Flag.SOM_LEFTMOST | ps.Flag.DOTALL)
+    db = ps.StreamDatabase(pattern, som_horizon="small")
+    scan = db.build(ctx, on_match, match_objects=True)
+
+    scan.scan(b"a")
+    scan.scan(b"x" * 2**17 + b"b")
+    match = on_match.call_args.args[1]
+    assert match.span() == (None, 2**17 + 2)
+    assert match.group() is None
+
+
+def test_pickled_database_keeps_patterns(ctx, on_match):
+    pattern = ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="foo", min_offset=3)
+    db = pickle.loads(pickle.dumps(ps.BlockDatabase(pattern)))
+    scan = db.build(ctx, on_match, match_objects=True)
+
+    scan.scan(b"foofoo")
+    match = on_match.call_args.args[1]
+    assert match.span() == (3, 6)
+    assert match.pattern.expression == b"foo"
+    assert match.pattern.flags == [ps.Flag.SOM_LEFTMOST]
+    assert match.pattern.tag == "foo"
+
+
+def test_scanner_pool_is_shared_between_threads(ctx, on_match):
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="foo"))
+    pool = db.build_pool(ctx, on_match)
+
+    with ThreadPoolExecutor(max_workers=4) as executor:
+        data = [b"foo" * i for i in range(100)]
+        results = list(executor.map(pool.scan_collect, data))
+        scans = list(executor.map(pool.scan, [b"barfoo"] * 10))
+
+    assert results == [[("foo", j, j + 3) for j in range(0, len(d), 3)] for d in data]
+    assert scans == [ps.Scan.Continue] * 10
+    assert on_match.call_count == 10
+    on_match.assert_called_with(ctx, "foo", 3, 6)
+
+
+@pytest.mark.parametrize("threads", [None, 1, 3, 200])
+def test_scan_many(threads):
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="foo"))
+    buffers = [b"foo" * (i % 3) for i in range(100)]
+
+    assert db.scan_many(buffers, threads=threads) == [
+        (i, "foo", j, j + 3)
+        for i, data in enumerate(buffers)
+        for j in range(0, len(data), 3)
+    ]
+
+
+def test_scan_many_needs_threads():
+    db = ps.BlockDatabase(ps.Pattern(b"foo"))
+
+    with pytest.raises(ValueError, match="threads"):
+        db.scan_many([b"foo"], threads=0)
+
+
+def test_stream_close_reports_end_of_data_matches(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo$", tag="foo"))
+    scan = db.build(ctx, on_match)
+
+    scan.scan(b"barfoo")
+    on_match.assert_not_called()
+    assert scan.close() == ps.Scan.Continue
+    on_match.assert_called_once_with(ctx, "foo", 0, 6)
+    assert scan.closed
+    assert scan.close() == ps.Scan.Continue
+    on_match.assert_called_once()
+
+    with pytest.raises(ValueError, match="Stream is closed"):
+        scan.scan(b"foo")
+    with pytest.raises(ValueError, match="Stream is closed"):
+        scan.reset()
+
+
+def test_stream_scanner_is_context_manager(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo$", tag="foo"))
+
+    with db.build(ctx, on_match) as scan:
+        scan.scan(b"foo")
+        assert not scan.closed
+
+    assert scan.closed
+    on_match.assert_called_once_with(ctx, "foo", 0, 3)
+
+
+def test_stream_copy(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foobar", ps.Flag.SOM_LEFTMOST, tag="foo"))
+    scan = db.build(ctx, on_match)
+    scan.scan(b"xfoo")
+
+    copy = scan.copy()
+    copy.scan(b"bar")
+    on_match.assert_called_once_with(ctx, "foo", 1, 7)
+    scan.scan(b"baz")
+    on_match.assert_called_once()
+    scan.reset()
+    copy.scan(b"foobar")
+    on_match.assert_called_with(ctx, "foo", 7, 13)
+
+
+def test_stream_reset_from(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo$", tag="foo"))
+    scan = db.build(ctx, on_match)
+    other = db.build(ctx, on_match)
+    other.scan(b"barf")
+
+    scan.scan(b"foo")
+    assert scan.reset_from(other) == ps.Scan.Continue
+    on_match.assert_called_once_with(ctx, "foo", 0, 3)
+    scan.scan(b"oo")
+    scan.close()
+    on_match.assert_called_with(ctx, "foo", 0, 6)
+
+
+def test_stream_reset_from_is_validated(ctx, on_match):
+    scan = ps.StreamDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+    other = ps.StreamDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    with pytest.raises(ValueError, match="different database"):
+        scan.reset_from(other)
+    with pytest.raises(ValueError, match="itself"):
+        scan.reset_from(scan)
+
+
+def test_stream_save_and_restore_state(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foobar", ps.Flag.SOM_LEFTMOST, tag="foo"))
+    scan = db.build(ctx, on_match)
+    scan.scan(b"xfoo")
+    state = scan.save_state()
+    scan.close()
+
+    restored = pickle.loads(pickle.dumps(db)).build(ctx, on_match)
+    assert restored.restore_state(state) == ps.Scan.Continue
+    restored.scan(b"bar")
+    on_match.assert_called_once_with(ctx, "foo", 1, 7)
+
+    assert scan.restore_state(state) == ps.Scan.Continue
+    assert not scan.closed
+    scan.scan(b"bar")
+    on_match.assert_called_with(ctx, "foo", 1, 7)
+
+
+def test_stream_restore_state_checks_database(ctx, on_match):
+    scan = ps.StreamDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+    other = ps.StreamDatabase(ps.Pattern(b"bar")).build(ctx, on_match)
+
+    with pytest.raises(ValueError, match="different database"):
+        other.restore_state(scan.save_state())
+    with pytest.raises(ValueError, match="different database"):
+        other.restore_state(scan.save_state()[:16])
+
+
+@pytest.mark.parametrize("length", [0, 4, 8, 12])
+def test_stream_restore_state_rejects_truncated_state(length, ctx, on_match):
+    scan = ps.StreamDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    with pytest.raises(ValueError, match="truncated"):
+        scan.restore_state(scan.save_state()[:length])
+
+
+def test_stream_table(ctx, on_match):
+    foo = ps.Pattern(b"foo", tag="foo")
+    bar = ps.Pattern(b"bar$", tag="bar")
+    db = ps.StreamDatabase(foo, bar)
+    table = db.build_table(ctx, on_match)
+    table.open("a")
+    table.open(("b", 1))
+
+    table.scan("a", b"f")
+    table.scan(("b", 1), b"xf")
+    table.scan("a", b"oo")
+    on_match.assert_called_once_with(ctx, "a", "foo", 0, 3)
+    table.scan(("b", 1), b"oobar")
+    on_match.assert_called_with(ctx, ("b", 1), "foo", 0, 4)
+
+    assert len(table) == 2
+    assert table.close(("b", 1)) == ps.Scan.Continue
+    on_match.assert_called_with(ctx, ("b", 1), "bar", 0, 7)
+    assert ("b", 1) not in table
+    assert "a" in table
+    with pytest.raises(KeyError):
+        table.scan(("b", 1), b"foo")
+    with pytest.raises(ValueError, match="already open"):
+        table.open("a")
+
+
+def test_stream_table_evicts_streams(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo$", tag="foo"))
+    table = db.build_table(ctx, on_match, max_streams=2)
+    table.open("a")
+    table.open("b")
+    table.scan("b", b"foo")
+    table.scan("a", b"foo")
+
+    table.open("c")
+    on_match.assert_called_once_with(ctx, "b", "foo", 0, 3)
+    assert "b" not in table
+    assert table.evict_idle(0) == 2
+    assert len(table) == 0
+    on_match.assert_called_with(ctx, "a", "foo", 0, 3)
+
+
+def test_stream_table_evicts_all_streams_on_error(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo$", tag="foo"))
+    table = db.build_table(ctx, on_match)
+    table.open("a")
+    table.open("b")
+    table.scan("a", b"foo")
+    table.scan("b", b"foo")
+    on_match.side_effect = [RuntimeError("boom"), None]
+
+    with pytest.raises(RuntimeError, match="boom"):
+        table.evict_idle(0)
+    assert len(table) == 0
+    on_match.assert_called_with(ctx, "b", "foo", 0, 3)
+
+
+class FlakyKey:
+    fail = False
+
+    def __hash__(self):
+        if self.fail:
+            raise RuntimeError("unhashable")
+        return 0
+
+
+def test_stream_table_keeps_stream_if_key_cannot_be_removed(ctx, on_match):
+    table = ps.StreamDatabase(ps.Pattern(b"foo")).build_table(ctx, on_match)
+    key = FlakyKey()
+    table.open(key)
+
+    key.fail = True
+    with pytest.raises(RuntimeError, match="unhashable"):
+        table.evict_idle(0)
+    key.fail = False
+    assert key in table
+    table.scan(key, b"foo")
+    on_match.assert_called_once_with(ctx, key, 0, 0, 3)
+    table.close(key)
+    assert len(table) == 0
+
+
+@pytest.mark.parametrize("content", [b"", b"xfoo"])
+def test_scan_file(content, tmp_path, ctx, on_match):
+    path = tmp_path / "data"
+    path.write_bytes(content)
+    db = ps.BlockDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="foo"))
+    scan = db.build(ctx, on_match, match_objects=True)
+
+    assert scan.scan_file(path) == ps.Scan.Continue
+    matches = [c.args[1].group() for c in on_match.call_args_list]
+    assert matches == ([b"foo"] if content else [])
+
+
+@pytest.mark.skipif(not hasattr(os, "mkfifo"), reason="needs FIFO support")
+def test_scan_file_reads_files_without_size(tmp_path, ctx, on_match):
+    path = tmp_path / "fifo"
+    os.mkfifo(path)
+    scan = ps.BlockDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    def write():
+        with path.open("wb") as f:
+            f.write(b"xfoo")
+
+    with ThreadPoolExecutor(1) as executor:
+        executor.submit(write)
+        assert scan.scan_file(path) == ps.Scan.Continue
+    on_match.assert_called_once_with(ctx, 0, 0, 4)
+
+
+def test_scan_reader(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo", ps.Flag.SOM_LEFTMOST, tag="foo"))
+    scan = db.build(ctx, on_match)
+
+    reader = io.BytesIO(b"barfoo" * 10)
+
+    assert scan.scan_reader(reader, chunk_size=4) == ps.Scan.Continue
+    assert on_match.call_args_list == [
+        mock.call(ctx, "foo", i, i + 3) for i in range(3, 60, 6)
+    ]
+
+
+@pytest.mark.parametrize("method", ["scan", "scan_collect"])
+def test_stream_chunk_size_must_be_positive(method, ctx, on_match):
+    scan = ps.StreamDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    with pytest.raises(ValueError, match="chunk_size must be positive"):
+        getattr(scan, method)(b"foo", chunk_size=0)
+    with pytest.raises(ValueError, match="chunk_size must be positive"):
+        scan.scan_reader(io.BytesIO(b"foo"), chunk_size=0)
+    on_match.assert_not_called()
+
+
+def test_scan_reader_can_be_aborted(ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo", tag="foo"))
+    scan = db.build(ctx, on_match)
+    on_match.return_value = ps.Scan.Terminate
+    reader = io.BytesIO(b"foo" * 10)
+
+    assert scan.scan_reader(reader, chunk_size=4) == ps.Scan.Terminate
+    assert reader.tell() == 4
+
+
+FOUR_GIB = 2**32
+
+
+@pytest.fixture
+def huge_buffer(tmp_path):
+    if sys.maxsize <= FOUR_GIB:
+        pytest.skip("needs a 64-bit address space")
+    path = tmp_path / "huge"
+    with path.open("wb") as f:
+        # Straddles the largest chunk a single Hyperscan call can scan.
+        f.seek(FOUR_GIB - 2)
+        f.write(b"foo")
+    with path.open("rb") as f, mmap.mmap(f.fileno(), 0, access=mmap.ACCESS_READ) as mm:
+        yield mm
+
+
+def test_block_scan_rejects_huge_buffer(huge_buffer, ctx, on_match):
+    scan = ps.BlockDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    with pytest.raises(ValueError, match="4 GiB"):
+        scan.scan(huge_buffer)
+    on_match.assert_not_called()
+
+
+def test_vectored_scan_rejects_huge_element(huge_buffer, ctx, on_match):
+    scan = ps.VectoredDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+
+    with pytest.raises(ValueError, match="4 GiB"):
+        scan.scan([b"foo", huge_buffer])
+    on_match.assert_not_called()
+
+
+def test_stream_scan_splits_huge_buffer(huge_buffer, ctx, on_match):
+    db = ps.StreamDatabase(ps.Pattern(b"foo", tag="foo"))
+    scan = db.build(ctx, on_match)
+
+    assert scan.scan(huge_buffer) == ps.Scan.Continue
+    on_match.assert_called_once_with(ctx, "foo", 0, FOUR_GIB + 1)
+
+
+class Handler:
+    def on_match(self, *_):
+        return ps.Scan.Continue
+
+
+def assert_collected(obj):
+    ref = weakref.ref(obj)
+    del obj
+    gc.collect()
+    assert ref() is None
+
+
+@pytest.mark.parametrize(
+    "database",
+    [ps.BlockDatabase, ps.VectoredDatabase, ps.StreamDatabase],
+    indirect=True,
+)
+def test_scanner_reference_cycle_is_collected(database):
+    handler = Handler()
+    handler.scanner = database.build(None, handler.on_match)
+    assert_collected(handler)
+
+
+def test_scanner_pool_reference_cycle_is_collected():
+    handler = Handler()
+    handler.pool = ps.BlockDatabase(ps.Pattern(b"foo")).build_pool(
+        None, handler.on_match
+    )
+    handler.pool.scan(b"foo")
+    assert_collected(handler)
+
+
+def test_stream_table_reference_cycle_is_collected():
+    handler = Handler()
+    handler.table = ps.StreamDatabase(ps.Pattern(b"foo")).build_table(
+        None, handler.on_match
+    )
+    handler.table.open(handler)
+    assert_collected(handler)
+
+
+def test_database_tag_reference_cycle_is_collected():
+    handler = Handler()
+    handler.database = ps.BlockDatabase(ps.Pattern(b"foo", tag=handler))
+    assert_collected(handler)
+
+
+def test_scan_reader_buffer_outlives_the_scan(ctx, on_match):
+    views = []
+
+    class Reader(io.BytesIO):
+        def readinto(self, buffer):
+            views.append(memoryview(buffer))
+            return super().readinto(buffer)
+
+    scan = ps.StreamDatabase(ps.Pattern(b"foo")).build(ctx, on_match)
+    assert scan.scan_reader(Reader(b"foo"), chunk_size=4) == ps.Scan.Continue
+    on_match.assert_called_once_with(ctx, 0, 0, 3)
+
+    views[0][:3] = b"bar"
+    assert views[0][:3] == b"bar"